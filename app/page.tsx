@@ -9,36 +9,63 @@ import { message } from '@tauri-apps/api/dialog';
 import { getVersion } from "@tauri-apps/api/app";
 import { Store } from "tauri-plugin-store-api";
 
+type Whisper = { direction: "incoming" | "outgoing" | "unknown", partner: string };
+type ChatMessage = { seq: number, source: string, speaker: string | null, whisper: Whisper | null, text: string, color: string, channel: string, time: string, backfilled: boolean };
+type ChannelLog = { channel: string, messages: ChatMessage[], updated: boolean, paged?: boolean };
+type MessagesRead = { logs: ChannelLog[], last: number | null };
+type MessagesAppended = { since: number | null, last: number | null, messages: ChatMessage[], limit: number | null };
+
+// Joins two runs of messages in seq order, each message once.
+const mergeMessages = (a: ChatMessage[], b: ChatMessage[]): ChatMessage[] => {
+  const merged = a.concat(b).sort((x, y) => x.seq - y.seq);
+  return merged.filter((message, i) => i === 0 || merged[i - 1].seq !== message.seq);
+};
+
+// A view with older history paged in is not trimmed until it is scrolled
+// back to the bottom.
+const appendMessages = (logs: ChannelLog[], appended: MessagesAppended): ChannelLog[] => {
+  return logs.map((log, i) => {
+    const messages = appended.messages.filter(message => i === 0 || JSON.stringify(message.channel) === JSON.stringify(log.channel));
+    if (messages.length === 0) {
+      return { ...log, updated: false };
+    }
+    const merged = mergeMessages(log.messages, messages);
+    return { ...log, messages: appended.limit === null || log.paged ? merged : merged.slice(-appended.limit), updated: true };
+  });
+};
+
+const formatTime = (time: string): string => {
+  const [hour, minute, second] = time.substring(11, 19).split(":").map(e => parseInt(e).toString().padStart(2, " "));
+  return `[${hour}時 ${minute}分 ${second}秒]`;
+};
+
+const PAGE_SIZE = 100;
+
+const formatMessage = (message: ChatMessage): string => {
+  if (message.whisper?.direction === "outgoing") {
+    return `${message.whisper.partner}へ : ${message.text}`;
+  }
+  return message.speaker === null ? message.text : `${message.speaker} : ${message.text}`;
+};
+
 export default function Home() {
-  const names = ["全体", "一般", "耳打ち", "チーム", "クラブ", "システム", "叫び"];
+  const [names, setNames] = useState(["全体", "一般", "耳打ち", "チーム", "クラブ", "システム", "叫び"]);
   const init = useRef(false);
   const expRef = useRef((null as unknown) as HTMLDivElement);
-  const labelRefs = [
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-    useRef((null as unknown) as HTMLSpanElement),
-  ];
-  const messageRefs = [
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-    useRef((null as unknown) as HTMLDivElement),
-  ];
+  const labelRefs = useRef([] as HTMLSpanElement[]);
+  const messageRefs = useRef([] as HTMLDivElement[]);
   const spacerRef = useRef((null as unknown) as HTMLDivElement);
-  const [messages, setMessages] = useState<[[string, string, string][], boolean][]>([...Array(names.length)].map(_ => [[], false]));
+  const [messages, setMessages] = useState<ChannelLog[]>([...Array(names.length)].map(_ => ({ channel: "", messages: [], updated: false })));
   const [views, setViews] = useState([...Array(names.length)].map(_ => true));
   const [exp, setExp] = useState([0, 0, 0]);
   const [expVisible, setExpVisible] = useState(false);
   const [verbose, setVerbose] = useState(false);
   const [vertical, setVertical] = useState(true);
   const [autoScroll, setAutoScroll] = useState([...Array(names.length).map(_ => true)]);
+  const [multiSource, setMultiSource] = useState(false);
+  const loading = useRef([] as boolean[]);
+  const limit = useRef<number | null>(null);
+  const lastSeq = useRef<number | null>(null);
 
   useEffect(() => {
     const resizeViewImpl = () => {
@@ -49,12 +76,12 @@ export default function Home() {
         }
         if (vertical) {
           const expHeight = expRef.current === null ? 0 : expRef.current.offsetHeight;
-          const height = (window.innerHeight - expHeight - labelRefs[i].current.offsetHeight * view_count - spacerRef.current.offsetHeight) / view_count;
-          messageRefs[i].current.style.height = height + "px";
+          const height = (window.innerHeight - expHeight - labelRefs.current[i].offsetHeight * view_count - spacerRef.current.offsetHeight) / view_count;
+          messageRefs.current[i].style.height = height + "px";
         } else {
           const expHeight = expRef.current === null ? 0 : expRef.current.offsetHeight;
-          const height = window.innerHeight - expHeight - labelRefs[i].current.offsetHeight - spacerRef.current.offsetHeight;
-          messageRefs[i].current.style.height = height + "px";
+          const height = window.innerHeight - expHeight - labelRefs.current[i].offsetHeight - spacerRef.current.offsetHeight;
+          messageRefs.current[i].style.height = height + "px";
         }
       }
     };
@@ -63,6 +90,18 @@ export default function Home() {
     window.addEventListener("resize", resizeView);
 
     const f = async () => {
+      type ChannelInfo = { channel: any, name: string };
+      const channels = await invoke("get_channels") as ChannelInfo[];
+      setNames(channels.map(e => e.name));
+      type SourceConfig = { name: string, log_dir: string };
+      const updateSources = async () => {
+        const sources = await invoke("get_sources") as SourceConfig[];
+        setMultiSource(sources.length > 1);
+      };
+      await updateSources();
+      await listen('sources', async event => {
+        setMultiSource((event.payload as SourceConfig[]).length > 1);
+      });
       document.addEventListener('contextmenu', event => {
         event.preventDefault();
       });
@@ -70,7 +109,7 @@ export default function Home() {
         if (event.key !== "F3" && !(event.ctrlKey && event.key === "f")) {
           event.preventDefault();
         }
-        for (let i = 0; i < names.length; ++i) {
+        for (let i = 0; i < channels.length; ++i) {
           if (!event.ctrlKey && !event.shiftKey && !event.altKey && event.key === (i + 1).toString() && !event.repeat) {
             const store_name = await invoke("get_store_name") as string;
             const store = new Store(store_name);
@@ -123,11 +162,44 @@ export default function Home() {
           setVertical(value);
         }
       });
+      const isStale = (appended: MessagesAppended) => {
+        return appended.last === null || (lastSeq.current !== null && appended.last <= lastSeq.current);
+      };
+      const applyAppended = (appended: MessagesAppended) => {
+        if (isStale(appended)) {
+          return;
+        }
+        limit.current = appended.limit;
+        lastSeq.current = appended.last;
+        setMessages(prev => appendMessages(prev, appended));
+      };
+      // Fetches whatever came after the last message seen, e.g. after a missed
+      // event. Messages already shown are merged away by seq.
+      const resync = async () => {
+        applyAppended(await invoke("get_messages_since", { seq: lastSeq.current }) as MessagesAppended);
+      };
+      setMessages(channels.map(e => ({ channel: e.channel, messages: [], updated: false })));
       await listen('read', async event => {
-        setMessages(event.payload as [[[string, string, string][], boolean]]);
+        const read = event.payload as MessagesRead;
+        lastSeq.current = read.last;
+        setMessages(read.logs);
+        await resync();
+        await updateSources();
+      });
+      await listen('messages_appended', async event => {
+        const appended = event.payload as MessagesAppended;
+        if (isStale(appended)) {
+          return;
+        }
+        if (appended.since !== lastSeq.current) {
+          await resync();
+          return;
+        }
+        applyAppended(appended);
       });
+      await resync();
       type State = { views: boolean[], exp: boolean, auto_scroll: boolean[], verbose: boolean, vertical: boolean };
-      for (let i = 0; i < names.length; ++i) {
+      for (let i = 0; i < channels.length; ++i) {
         await listen('view' + i, async event => {
           const state = await invoke("get_state") as State;
           setViews(state.views);
@@ -157,7 +229,10 @@ export default function Home() {
         await message(`バージョン: ${version}\n開発者X: @JADEN_tales`, { title: "Neosについて" });
       });
       await listen('error', async event => {
-        await message("エラーが発生しました。ソフトを再起動してください。", { title: "エラー", type: "error" });
+        await message(`ログを読み込めません。読み込めるようになるまで再試行します。\n${event.payload as string}`, { title: "エラー", type: "error" });
+      });
+      await listen('archive_error', async event => {
+        await message(event.payload as string, { title: "アーカイブ", type: "warning" });
       });
       const state = await invoke("get_state") as State;
       setExpVisible(state.exp);
@@ -178,11 +253,11 @@ export default function Home() {
 
   useEffect(() => {
     for (let i = 0; i < names.length; ++i) {
-      if (!views[i] || !autoScroll[i] || !messages[i][1]) {
+      if (!views[i] || !autoScroll[i] || !messages[i]?.updated) {
         continue;
       }
-      if (messageRefs[i].current !== null) {
-        messageRefs[i].current.scrollTop = messageRefs[i].current.scrollHeight;
+      if (messageRefs.current[i]) {
+        messageRefs.current[i].scrollTop = messageRefs.current[i].scrollHeight;
       }
     }
   }, [messages]);
@@ -192,14 +267,14 @@ export default function Home() {
       if (!views[i]) {
         continue;
       }
-      if (messageRefs[i].current !== null) {
-        messageRefs[i].current.scrollTop = messageRefs[i].current.scrollHeight;
+      if (messageRefs.current[i]) {
+        messageRefs.current[i].scrollTop = messageRefs.current[i].scrollHeight;
       }
     }
   }, [vertical]);
 
   const toggleAutoScroll = async (event: React.MouseEvent<HTMLDivElement>) => {
-    const i = parseInt(event.currentTarget.id[event.currentTarget.id.length - 1]);
+    const i = parseInt(event.currentTarget.id.substring("auto_scroll".length));
     const store_name = await invoke("get_store_name") as string;
     const store = new Store(store_name);
     await store.load();
@@ -210,6 +285,51 @@ export default function Home() {
     setAutoScroll(prev => prev.map((e, j) => i === j ? !e : e));
   };
 
+  // Pages in older messages when a view is scrolled to the top, one page at a
+  // time.
+  const loadOlder = async (i: number) => {
+    const view = messageRefs.current[i];
+    const log = messages[i];
+    if (loading.current[i] || !view || !log || log.messages.length === 0) {
+      return;
+    }
+    loading.current[i] = true;
+    const older = await invoke("get_messages_range", { channel: log.channel, beforeSeq: log.messages[0].seq, limit: PAGE_SIZE }).catch(() => []) as ChatMessage[];
+    if (older.length === 0) {
+      loading.current[i] = false;
+      return;
+    }
+    const height = view.scrollHeight;
+    setMessages(prev => prev.map((e, j) => j === i ? { ...e, messages: mergeMessages(older, e.messages), updated: false, paged: true } : e));
+    requestAnimationFrame(() => {
+      view.scrollTop = view.scrollHeight - height;
+      loading.current[i] = false;
+    });
+  };
+
+  // Drops the paged in history again once the view is back at the bottom.
+  const dropOlder = (i: number) => {
+    const view = messageRefs.current[i];
+    const n = limit.current;
+    if (!view || n === null || !messages[i]?.paged) {
+      return;
+    }
+    setMessages(prev => prev.map((e, j) => j === i ? { ...e, messages: e.messages.slice(-n), paged: false } : e));
+    requestAnimationFrame(() => { view.scrollTop = view.scrollHeight; });
+  };
+
+  const onScroll = (i: number) => {
+    const view = messageRefs.current[i];
+    if (!view) {
+      return;
+    }
+    if (view.scrollTop === 0) {
+      loadOlder(i);
+    } else if (view.scrollHeight - view.clientHeight - view.scrollTop < 1) {
+      dropOlder(i);
+    }
+  };
+
   const toCommaString = (value: number): string => {
     const s = value.toString().split("").reverse().join("");
     const commaNum = Math.trunc((s.length - 1) / 3);
@@ -236,16 +356,17 @@ export default function Home() {
       {vertical && names.map((name, i) => {
         return views[i] && (
           <div key={name}>
-            <span className={`d-inline-block pt-1 pb-1 ${styles["view-label"]}`} ref={labelRefs[i]}>{name}</span>
+            <span className={`d-inline-block pt-1 pb-1 ${styles["view-label"]}`} ref={e => { labelRefs.current[i] = e as HTMLSpanElement; }}>{name}</span>
             <span className={`ms-3 ${autoScroll[i] ? "" : "opacity-25"}`} onClick={toggleAutoScroll} id={"auto_scroll" + i}>
               <i className="bi bi-card-text text-light"></i>
               <i className="bi bi-arrow-down-short text-light"></i>
             </span>
-            <div className={styles.view} style={{overflow: "auto"}} ref={messageRefs[i]}>
+            <div className={styles.view} style={{overflow: "auto"}} onScroll={() => onScroll(i)} ref={e => { messageRefs.current[i] = e as HTMLDivElement; }}>
               {
-                messages[i][0].map((e, j) => {
-                  const message = verbose ? e[2] + " " + e[0] : e[0];
-                  return <div key={j} style={{color: e[1]}}>{message}</div>;
+                (messages[i]?.messages ?? []).map(e => {
+                  const text = multiSource ? `[${e.source}] ${formatMessage(e)}` : formatMessage(e);
+                  const message = verbose ? formatTime(e.time) + " " + text : text;
+                  return <div key={e.seq} className={e.backfilled ? "opacity-75" : ""} style={{color: e.color}}>{message}</div>;
                 })
               }
             </div>
@@ -258,17 +379,18 @@ export default function Home() {
             return views[i] && (
               <div className="col" key={name}>
                 <div>
-                  <span className={`d-inline-block pt-1 pb-1 ${styles["view-label"]}`} ref={labelRefs[i]}>{name}</span>
+                  <span className={`d-inline-block pt-1 pb-1 ${styles["view-label"]}`} ref={e => { labelRefs.current[i] = e as HTMLSpanElement; }}>{name}</span>
                   <span className={`ms-3 ${autoScroll[i] ? "" : "opacity-25"}`} onClick={toggleAutoScroll} id={"auto_scroll" + i}>
                     <i className="bi bi-card-text text-light"></i>
                     <i className="bi bi-arrow-down-short text-light"></i>
                   </span>
                 </div>
-                <div className={styles.view} style={{overflow: "auto"}} ref={messageRefs[i]}>
+                <div className={styles.view} style={{overflow: "auto"}} onScroll={() => onScroll(i)} ref={e => { messageRefs.current[i] = e as HTMLDivElement; }}>
                   {
-                    messages[i][0].map((e, j) => {
-                      const message = verbose ? e[2] + " " + e[0] : e[0];
-                      return <div key={j} style={{color: e[1]}}>{message}</div>;
+                    (messages[i]?.messages ?? []).map(e => {
+                      const text = multiSource ? `[${e.source}] ${formatMessage(e)}` : formatMessage(e);
+                      const message = verbose ? formatTime(e.time) + " " + text : text;
+                      return <div key={e.seq} className={e.backfilled ? "opacity-75" : ""} style={{color: e.color}}>{message}</div>;
                     })
                   }
                 </div>
@@ -0,0 +1,165 @@
+use std::{fs, path::{Path, PathBuf}};
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use rusqlite::{params, Connection};
+use crate::{Channel, ChatMessage};
+
+// Each one builds on the schema left by those before it. How many have been
+// applied is kept in the database's user_version.
+const MIGRATIONS: [&str; 1] = [
+  "CREATE TABLE messages (
+    id INTEGER PRIMARY KEY,
+    file TEXT NOT NULL,
+    file_offset INTEGER NOT NULL,
+    raw TEXT NOT NULL,
+    source TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    color TEXT NOT NULL,
+    speaker TEXT,
+    whisper TEXT,
+    text TEXT NOT NULL,
+    time TEXT NOT NULL,
+    UNIQUE (file, file_offset, raw)
+  );
+  CREATE INDEX messages_time ON messages (time);",
+];
+
+// A message and the line of the log file it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+  pub file: PathBuf,
+  pub offset: u64,
+  pub raw: String,
+  pub message: ChatMessage,
+}
+
+// Every message read, kept across runs. A line is stored once however many
+// times its file is read again, e.g. on the next start with backfill on. It
+// is told apart by its text as well as its place, since a truncated or
+// replaced file puts new lines where old ones were.
+#[derive(Debug)]
+pub struct Archive {
+  connection: Connection,
+}
+
+impl Archive {
+  pub fn open(path: &Path) -> Result<Self> {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let connection = Connection::open(path).with_context(|| format!("{} を開けません。", path.display()))?;
+    Archive::migrate(connection).with_context(|| format!("{} を開けません。", path.display()))
+  }
+
+  pub fn open_in_memory() -> Result<Self> {
+    Archive::migrate(Connection::open_in_memory()?)
+  }
+
+  fn migrate(mut connection: Connection) -> Result<Self> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if MIGRATIONS.len() < version {
+      bail!("新しいバージョンのアーカイブです。");
+    }
+    let transaction = connection.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+      transaction.execute_batch(migration)?;
+      transaction.pragma_update(None, "user_version", i + 1)?;
+    }
+    transaction.commit()?;
+    Ok(Archive { connection })
+  }
+
+  // Writes a batch in one transaction and returns how many were new.
+  pub fn insert(&mut self, entries: &[ArchiveEntry]) -> Result<usize> {
+    if entries.is_empty() {
+      return Ok(0);
+    }
+    let transaction = self.connection.transaction()?;
+    let mut inserted = 0;
+    {
+      let mut statement = transaction.prepare_cached(
+        "INSERT OR IGNORE INTO messages (file, file_offset, raw, source, channel, color, speaker, whisper, text, time)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+      )?;
+      for entry in entries {
+        let message = &entry.message;
+        let whisper = message.whisper.as_ref().map(serde_json::to_string).transpose()?;
+        inserted += statement.execute(params![
+          entry.file.to_string_lossy(),
+          entry.offset,
+          entry.raw,
+          message.source,
+          serde_json::to_string(&message.channel)?,
+          message.color,
+          message.speaker,
+          whisper,
+          message.text,
+          message.time.to_rfc3339(),
+        ])?;
+      }
+    }
+    transaction.commit()?;
+    Ok(inserted)
+  }
+
+  // The messages of a log file in the order of its lines, numbered by when
+  // they were first stored.
+  pub fn messages(&self, file: &Path) -> Result<Vec<ChatMessage>> {
+    let mut statement = self.connection.prepare_cached(
+      "SELECT id, source, channel, color, speaker, whisper, text, time FROM messages WHERE file = ?1 ORDER BY file_offset, id",
+    )?;
+    let rows = statement.query_map([file.to_string_lossy()], |row| {
+      Ok((row.get::<_, u64>(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get(3)?, row.get(4)?, row.get::<_, Option<String>>(5)?, row.get(6)?, row.get::<_, String>(7)?))
+    })?;
+    let mut messages = Vec::new();
+    for row in rows {
+      let (seq, source, channel, color, speaker, whisper, text, time) = row?;
+      let channel: Channel = serde_json::from_str(&channel)?;
+      let whisper = whisper.map(|whisper| serde_json::from_str(&whisper)).transpose()?;
+      let time = DateTime::parse_from_rfc3339(&time)?;
+      messages.push(ChatMessage { seq, source, speaker, whisper, text, color, channel, time, backfilled: false });
+    }
+    Ok(messages)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{testing::{message, TempDir}, Whisper, WhisperDirection};
+
+  #[test]
+  fn insert_once() {
+    let dir = TempDir::new("archive");
+    let path = dir.path().join("archive.sqlite3");
+    let whisper = Whisper { partner: "JADEN".to_string(), direction: WhisperDirection::Incoming };
+    let message = ChatMessage { speaker: Some("JADEN".to_string()), whisper: Some(whisper), ..message(0, "一", "#64ff64", Channel::Whisper, (10, 0, 0)) };
+    let file = PathBuf::from("TWChatLog_2024_04_20.html");
+    let entry = |offset, message: &ChatMessage| ArchiveEntry { file: file.clone(), offset, raw: message.text.clone(), message: message.clone() };
+    let custom = ChatMessage { seq: 1, speaker: None, whisper: None, channel: Channel::Custom("guild".to_string()), text: "二".to_string(), ..message.clone() };
+
+    let mut archive = Archive::open(&path).unwrap();
+    assert_eq!(archive.insert(&[entry(100, &message), entry(200, &custom)]).unwrap(), 2);
+    drop(archive);
+    let mut archive = Archive::open(&path).unwrap();
+    let moved = ChatMessage { seq: 7, ..custom.clone() };
+    assert_eq!(archive.insert(&[entry(200, &moved), entry(300, &moved)]).unwrap(), 1);
+    // A new line where an old one was, e.g. after the file was truncated.
+    let replaced = ChatMessage { seq: 8, text: "三".to_string(), ..custom.clone() };
+    assert_eq!(archive.insert(&[entry(100, &replaced)]).unwrap(), 1);
+    assert_eq!(archive.messages(&file).unwrap(), [
+      ChatMessage { seq: 1, ..message },
+      ChatMessage { seq: 4, ..replaced },
+      ChatMessage { seq: 2, ..custom.clone() },
+      ChatMessage { seq: 3, ..custom },
+    ]);
+  }
+
+  #[test]
+  fn refuse_newer_schema() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+    assert!(Archive::migrate(connection).is_err());
+    assert!(Archive::open_in_memory().unwrap().messages(Path::new("none")).unwrap().is_empty());
+  }
+}
@@ -0,0 +1,63 @@
+use std::{fmt::Debug, sync::{Arc, Mutex}};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Asia::Tokyo;
+
+pub trait Clock: Debug + Send {
+  fn now(&self) -> DateTime<Utc>;
+
+  // Log files are named and dated by the game server's day.
+  fn today(&self) -> NaiveDate {
+    self.now().with_timezone(&Tokyo).date_naive()
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Utc> {
+    Utc::now()
+  }
+}
+
+// Clones share the same time, so a test can move the clock an App reads from.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+  now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+  pub fn new(now: DateTime<Utc>) -> Self {
+    ManualClock { now: Arc::new(Mutex::new(now)) }
+  }
+
+  pub fn set(&self, now: DateTime<Utc>) {
+    *self.now.lock().unwrap() = now;
+  }
+
+  pub fn advance(&self, duration: Duration) {
+    *self.now.lock().unwrap() += duration;
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> DateTime<Utc> {
+    *self.now.lock().unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn manual_clock() {
+    let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 4, 20, 14, 59, 59).unwrap());
+    let app_clock = clock.clone();
+    assert_eq!(app_clock.today(), NaiveDate::from_ymd_opt(2024, 4, 20).unwrap());
+    clock.advance(Duration::seconds(1));
+    assert_eq!(app_clock.now(), Utc.with_ymd_and_hms(2024, 4, 20, 15, 0, 0).unwrap());
+    assert_eq!(app_clock.today(), NaiveDate::from_ymd_opt(2024, 4, 21).unwrap());
+  }
+}
@@ -0,0 +1,188 @@
+use std::{collections::{BTreeMap, HashMap}, fs, path::Path};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::{Channel, DEFAULT_CAPACITY};
+
+const DEFAULT_COLORS: [(&str, &str); 9] = [
+  ("#c8ffc8", "public"),
+  ("#ffffff", "public"),
+  ("#64ff64", "whisper"),
+  ("#f7b73c", "team"),
+  ("#94ddfa", "club"),
+  ("#ff64ff", "system"),
+  ("#ff6464", "system"),
+  ("#64ff80", "system"),
+  ("#c896c8", "shout"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelConfig {
+  #[serde(default)]
+  pub channels: Vec<String>,
+  pub colors: BTreeMap<String, String>,
+  #[serde(default)]
+  pub capacity: BTreeMap<String, usize>,
+}
+
+impl Default for ChannelConfig {
+  fn default() -> Self {
+    ChannelConfig {
+      channels: Vec::new(),
+      colors: DEFAULT_COLORS.iter().map(|(color, channel)| (color.to_string(), channel.to_string())).collect(),
+      capacity: BTreeMap::new(),
+    }
+  }
+}
+
+impl ChannelConfig {
+  pub fn load(path: &Path) -> Result<Self> {
+    if !path.is_file() {
+      let config = ChannelConfig::default();
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+      }
+      fs::write(path, serde_json::to_string_pretty(&config)?)?;
+      return Ok(config);
+    }
+    let json = fs::read_to_string(path).with_context(|| format!("{} を読み込めません。", path.display()))?;
+    ChannelConfig::parse(&json).with_context(|| format!("{} の設定が正しくありません。", path.display()))
+  }
+
+  pub fn parse(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn color_map(&self) -> Result<ColorMap> {
+    let mut channels = Vec::new();
+    for name in self.channels.iter() {
+      let name = name.trim();
+      if name.is_empty() {
+        bail!("channels: チャンネル名が空です。");
+      }
+      if Channel::from_name(name).is_some() || name == "all" || name == "unknown" {
+        bail!("channels: \"{}\" は組み込みのチャンネル名です。", name);
+      }
+      let channel = Channel::Custom(name.to_string());
+      if channels.contains(&channel) {
+        bail!("channels: \"{}\" が重複しています。", name);
+      }
+      channels.push(channel);
+    }
+
+    let mut colors = HashMap::new();
+    for (color, name) in self.colors.iter() {
+      let normalized = color.trim().to_lowercase();
+      if normalized.len() != 7 || !normalized.starts_with('#') || !normalized[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("colors: \"{}\" は #rrggbb 形式の色ではありません。", color);
+      }
+      let channel = match Channel::from_name(name.trim()) {
+        Some(channel) => channel,
+        _ => {
+          let channel = Channel::Custom(name.trim().to_string());
+          if !channels.contains(&channel) {
+            bail!("colors: \"{}\" のチャンネル \"{}\" がありません。", color, name);
+          }
+          channel
+        }
+      };
+      if colors.insert(normalized, channel).is_some() {
+        bail!("colors: \"{}\" が重複しています。", color);
+      }
+    }
+
+    let mut capacities = HashMap::new();
+    for (name, capacity) in self.capacity.iter() {
+      let channel = match name.trim() {
+        "all" => Channel::All,
+        name => match Channel::from_name(name) {
+          Some(channel) => channel,
+          _ if channels.contains(&Channel::Custom(name.to_string())) => Channel::Custom(name.to_string()),
+          _ => bail!("capacity: チャンネル \"{}\" がありません。", name),
+        },
+      };
+      if *capacity == 0 {
+        bail!("capacity: \"{}\" は 1 以上にしてください。", name);
+      }
+      capacities.insert(channel, *capacity);
+    }
+    Ok(ColorMap { colors, channels, capacities })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorMap {
+  colors: HashMap<String, Channel>,
+  channels: Vec<Channel>,
+  capacities: HashMap<Channel, usize>,
+}
+
+impl Default for ColorMap {
+  fn default() -> Self {
+    ChannelConfig::default().color_map().unwrap()
+  }
+}
+
+impl ColorMap {
+  pub fn channel(&self, color: &str) -> Channel {
+    self.colors.get(&color.to_lowercase()).cloned().unwrap_or(Channel::Unknown)
+  }
+
+  pub fn custom_channels(&self) -> &[Channel] {
+    &self.channels
+  }
+
+  pub fn capacity(&self, channel: &Channel) -> usize {
+    self.capacities.get(channel).copied().unwrap_or(DEFAULT_CAPACITY)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_color_map() {
+    let map = ColorMap::default();
+    assert_eq!(map.channel("#c8ffc8"), Channel::Public);
+    assert_eq!(map.channel("#64FF64"), Channel::Whisper);
+    assert_eq!(map.channel("#c896c8"), Channel::Shout);
+    assert_eq!(map.channel("#123456"), Channel::Unknown);
+    assert!(map.custom_channels().is_empty());
+    assert_eq!(map.capacity(&Channel::All), DEFAULT_CAPACITY);
+  }
+
+  #[test]
+  fn custom_color_map() {
+    let config = ChannelConfig::parse(r##"{
+      "channels": ["取引"],
+      "colors": { "#C8FFC8": "team", "#123456": "取引", "#ff64ff": "system" },
+      "capacity": { "all": 50000, "取引": 100 }
+    }"##).unwrap();
+    let map = config.color_map().unwrap();
+    assert_eq!(map.channel("#c8ffc8"), Channel::Team);
+    assert_eq!(map.channel("#123456"), Channel::Custom("取引".to_string()));
+    assert_eq!(map.channel("#64ff64"), Channel::Unknown);
+    assert_eq!(map.custom_channels(), [Channel::Custom("取引".to_string())]);
+    assert_eq!(map.capacity(&Channel::All), 50000);
+    assert_eq!(map.capacity(&Channel::Custom("取引".to_string())), 100);
+    assert_eq!(map.capacity(&Channel::System), DEFAULT_CAPACITY);
+  }
+
+  #[test]
+  fn invalid_color_map() {
+    let invalid = [
+      r##"{ "colors": { "#12345": "public" } }"##,
+      r##"{ "colors": { "#123456": "trade" } }"##,
+      r##"{ "colors": { "#abcdef": "public", "#ABCDEF": "team" } }"##,
+      r##"{ "channels": ["public"], "colors": {} }"##,
+      r##"{ "channels": ["trade", "trade"], "colors": {} }"##,
+      r##"{ "colors": { "#123456": "all" } }"##,
+      r##"{ "colors": {}, "capacity": { "trade": 100 } }"##,
+      r##"{ "colors": {}, "capacity": { "public": 0 } }"##,
+    ];
+    for json in invalid {
+      assert!(ChannelConfig::parse(json).unwrap().color_map().is_err(), "{}", json);
+    }
+    assert!(ChannelConfig::parse(r##"{ "channels": [] }"##).is_err());
+  }
+}
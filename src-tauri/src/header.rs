@@ -0,0 +1,86 @@
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use crate::line::LINE;
+
+pub const HEADER_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogHeader {
+  pub date: NaiveDate,
+}
+
+impl LogHeader {
+  // Reads the header from the bytes at the start of a log file.
+  pub fn read(content: &[u8]) -> Option<LogHeader> {
+    let (text, _, _) = encoding_rs::SHIFT_JIS.decode(&content[..content.len().min(HEADER_SIZE as usize)]);
+    LogHeader::parse(&text)
+  }
+
+  pub fn parse(text: &str) -> Option<LogHeader> {
+    let mut lines = text.split("\r\n").filter(|line| line.trim() != "");
+    if !lines.next()?.starts_with("<body") || !lines.next()?.contains("Talesweaver Chat Message Log") {
+      return None;
+    }
+    let date = parse_date(lines.next()?)?;
+    Some(LogHeader { date })
+  }
+}
+
+static DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r##"Date : (\d+)年\s*(\d+)月\s*(\d+)日"##).unwrap());
+
+pub fn parse_date(line: &str) -> Option<NaiveDate> {
+  let captures = DATE.captures(line)?;
+  NaiveDate::from_ymd_opt(captures[1].parse().ok()?, captures[2].parse().ok()?, captures[3].parse().ok()?)
+}
+
+// Where the header block at the start of a file ends. Only whole lines count,
+// so a header still being written ends as far as it has got.
+pub fn header_end(content: &[u8]) -> u64 {
+  let mut end = 0;
+  for line in content.split_inclusive(|&byte| byte == b'\n') {
+    if !line.ends_with(b"\r\n") {
+      break;
+    }
+    let (text, _, _) = encoding_rs::SHIFT_JIS.decode(line);
+    let text = text.trim_end_matches("\r\n");
+    if !text.trim().is_empty() && (!is_header_line(text) || LINE.is_match(text)) {
+      break;
+    }
+    end += line.len() as u64;
+  }
+  end
+}
+
+fn is_header_line(line: &str) -> bool {
+  line.starts_with("<body")
+    || line.starts_with("<font face=")
+    || line.contains("Talesweaver Chat Message Log")
+    || parse_date(line).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{fixture, line, log_line};
+
+  #[test]
+  fn parse_header() {
+    let bytes = std::fs::read(fixture("TWChatLog_2024_04_20_no_data.html")).unwrap();
+    let (text, _, _) = encoding_rs::SHIFT_JIS.decode(&bytes);
+    assert_eq!(LogHeader::parse(&text), Some(LogHeader { date: NaiveDate::from_ymd_opt(2024, 4, 20).unwrap() }));
+    for line in text.split("\r\n").filter(|line| line.trim() != "") {
+      assert!(is_header_line(line));
+    }
+    assert_eq!(header_end(&bytes), bytes.len() as u64);
+    let chat = log_line("[ 0時  0分  0秒]", "Talesweaver Chat Message Log");
+    assert_eq!(header_end(&[&bytes[..], &chat[..]].concat()), bytes.len() as u64);
+    assert_eq!(header_end(&bytes[..bytes.len() - 1]), bytes.len() as u64 - 29);
+
+    let text = "<body bgcolor=\"black\">\r\n<font size=\"2\" color=\"white\"> <b> Talesweaver Chat Message Log </b></font></br>\r\n<font size=\"2\" color=\"white\"> <b> Date : 2024年   12月    1日 </b></font></br></br></br>\r\n";
+    assert_eq!(LogHeader::parse(text), Some(LogHeader { date: NaiveDate::from_ymd_opt(2024, 12, 1).unwrap() }));
+    assert_eq!(LogHeader::parse("<body bgcolor=\"black\">\r\n"), None);
+    assert_eq!(LogHeader::parse(""), None);
+    assert!(!is_header_line(&line("[ 0時  0分  0秒]", "#ff64ff", "い。")));
+  }
+}
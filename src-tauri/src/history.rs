@@ -0,0 +1,98 @@
+use std::{fs::{self, File}, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use crate::{header::{self, LogHeader}, jst, log_file_date, RawLine};
+
+// Only the start and the end of a file are read to find its time range.
+const PEEK_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogFileInfo {
+  pub name: String,
+  pub path: PathBuf,
+  pub date: NaiveDate,
+  pub size: u64,
+  pub first_time: Option<DateTime<FixedOffset>>,
+  pub last_time: Option<DateTime<FixedOffset>>,
+}
+
+pub fn list_logs(dir: &Path) -> Result<Vec<LogFileInfo>> {
+  let entries = fs::read_dir(dir).with_context(|| format!("{} を読み込めません。", dir.display()))?;
+  let mut logs = Vec::new();
+  for entry in entries.flatten() {
+    let name = match entry.file_name().to_str() {
+      Some(name) => name.to_string(),
+      _ => continue,
+    };
+    let date = match log_file_date(&name) {
+      Some(date) => date,
+      _ => continue,
+    };
+    // A file that cannot be read, e.g. one locked by another program, is left
+    // out rather than hiding the rest.
+    if let Ok(info) = log_file_info(&entry.path(), name, date) {
+      logs.push(info);
+    }
+  }
+  logs.sort_by_key(|log| std::cmp::Reverse(log.date));
+  Ok(logs)
+}
+
+fn log_file_info(path: &Path, name: String, date: NaiveDate) -> Result<LogFileInfo> {
+  let mut file = File::open(path)?;
+  let size = file.metadata()?.len();
+  let mut head = Vec::new();
+  (&mut file).take(header::HEADER_SIZE + PEEK_SIZE).read_to_end(&mut head)?;
+  let mut tail = Vec::new();
+  file.seek(SeekFrom::Start(size.saturating_sub(PEEK_SIZE)))?;
+  file.read_to_end(&mut tail)?;
+  // CR and LF are never Shift_JIS trail bytes, so decoding can start after one.
+  let tail = match tail.windows(2).position(|bytes| bytes == b"\r\n") {
+    Some(i) if PEEK_SIZE < size => &tail[i + 2..],
+    _ => &tail[..],
+  };
+
+  let (head, _, _) = encoding_rs::SHIFT_JIS.decode(&head);
+  let (tail, _, _) = encoding_rs::SHIFT_JIS.decode(tail);
+  let log_date = LogHeader::parse(&head).map(|header| header.date).unwrap_or(date);
+  let first = times(&head).next();
+  let last = times(&tail).last();
+  let first_time = first.map(|time| jst(log_date, time));
+  let last_time = last.map(|time| match first {
+    Some(first) if time < first => jst(log_date, time) + Duration::days(1),
+    _ => jst(log_date, time),
+  });
+  Ok(LogFileInfo { name, path: path.to_path_buf(), date, size, first_time, last_time })
+}
+
+fn times(text: &str) -> impl Iterator<Item = NaiveTime> + '_ {
+  text.split("\r\n").filter_map(move |line| RawLine::parse(line)?.time_of_day())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{fixture, fixture_dir, TempDir};
+
+  #[test]
+  fn list_test_logs() {
+    let logs = list_logs(&fixture_dir()).unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].name, "TWChatLog_2024_04_20.html");
+    assert_eq!(logs[0].date, NaiveDate::from_ymd_opt(2024, 4, 20).unwrap());
+    assert_eq!(logs[0].size, fs::metadata(&logs[0].path).unwrap().len());
+    assert_eq!(logs[0].first_time.unwrap().to_rfc3339(), "2024-04-20T00:00:00+09:00");
+    assert!(logs[0].first_time <= logs[0].last_time);
+  }
+
+  #[test]
+  fn skip_unreadable_logs() {
+    let dir = TempDir::new("history");
+    fs::create_dir_all(dir.path().join("TWChatLog_2024_04_21.html")).unwrap();
+    fs::copy(fixture("TWChatLog_2024_04_20.html"), dir.path().join("TWChatLog_2024_04_20.html")).unwrap();
+    let logs = list_logs(dir.path()).unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].name, "TWChatLog_2024_04_20.html");
+  }
+}
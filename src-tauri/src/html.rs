@@ -0,0 +1,387 @@
+// HTML 4 named character references plus &apos;, sorted for binary search.
+const ENTITIES: [(&str, char); 253] = [
+  ("AElig", '\u{c6}'),
+  ("Aacute", '\u{c1}'),
+  ("Acirc", '\u{c2}'),
+  ("Agrave", '\u{c0}'),
+  ("Alpha", '\u{391}'),
+  ("Aring", '\u{c5}'),
+  ("Atilde", '\u{c3}'),
+  ("Auml", '\u{c4}'),
+  ("Beta", '\u{392}'),
+  ("Ccedil", '\u{c7}'),
+  ("Chi", '\u{3a7}'),
+  ("Dagger", '\u{2021}'),
+  ("Delta", '\u{394}'),
+  ("ETH", '\u{d0}'),
+  ("Eacute", '\u{c9}'),
+  ("Ecirc", '\u{ca}'),
+  ("Egrave", '\u{c8}'),
+  ("Epsilon", '\u{395}'),
+  ("Eta", '\u{397}'),
+  ("Euml", '\u{cb}'),
+  ("Gamma", '\u{393}'),
+  ("Iacute", '\u{cd}'),
+  ("Icirc", '\u{ce}'),
+  ("Igrave", '\u{cc}'),
+  ("Iota", '\u{399}'),
+  ("Iuml", '\u{cf}'),
+  ("Kappa", '\u{39a}'),
+  ("Lambda", '\u{39b}'),
+  ("Mu", '\u{39c}'),
+  ("Ntilde", '\u{d1}'),
+  ("Nu", '\u{39d}'),
+  ("OElig", '\u{152}'),
+  ("Oacute", '\u{d3}'),
+  ("Ocirc", '\u{d4}'),
+  ("Ograve", '\u{d2}'),
+  ("Omega", '\u{3a9}'),
+  ("Omicron", '\u{39f}'),
+  ("Oslash", '\u{d8}'),
+  ("Otilde", '\u{d5}'),
+  ("Ouml", '\u{d6}'),
+  ("Phi", '\u{3a6}'),
+  ("Pi", '\u{3a0}'),
+  ("Prime", '\u{2033}'),
+  ("Psi", '\u{3a8}'),
+  ("Rho", '\u{3a1}'),
+  ("Scaron", '\u{160}'),
+  ("Sigma", '\u{3a3}'),
+  ("THORN", '\u{de}'),
+  ("Tau", '\u{3a4}'),
+  ("Theta", '\u{398}'),
+  ("Uacute", '\u{da}'),
+  ("Ucirc", '\u{db}'),
+  ("Ugrave", '\u{d9}'),
+  ("Upsilon", '\u{3a5}'),
+  ("Uuml", '\u{dc}'),
+  ("Xi", '\u{39e}'),
+  ("Yacute", '\u{dd}'),
+  ("Yuml", '\u{178}'),
+  ("Zeta", '\u{396}'),
+  ("aacute", '\u{e1}'),
+  ("acirc", '\u{e2}'),
+  ("acute", '\u{b4}'),
+  ("aelig", '\u{e6}'),
+  ("agrave", '\u{e0}'),
+  ("alefsym", '\u{2135}'),
+  ("alpha", '\u{3b1}'),
+  ("amp", '\u{26}'),
+  ("and", '\u{2227}'),
+  ("ang", '\u{2220}'),
+  ("apos", '\u{27}'),
+  ("aring", '\u{e5}'),
+  ("asymp", '\u{2248}'),
+  ("atilde", '\u{e3}'),
+  ("auml", '\u{e4}'),
+  ("bdquo", '\u{201e}'),
+  ("beta", '\u{3b2}'),
+  ("brvbar", '\u{a6}'),
+  ("bull", '\u{2022}'),
+  ("cap", '\u{2229}'),
+  ("ccedil", '\u{e7}'),
+  ("cedil", '\u{b8}'),
+  ("cent", '\u{a2}'),
+  ("chi", '\u{3c7}'),
+  ("circ", '\u{2c6}'),
+  ("clubs", '\u{2663}'),
+  ("cong", '\u{2245}'),
+  ("copy", '\u{a9}'),
+  ("crarr", '\u{21b5}'),
+  ("cup", '\u{222a}'),
+  ("curren", '\u{a4}'),
+  ("dArr", '\u{21d3}'),
+  ("dagger", '\u{2020}'),
+  ("darr", '\u{2193}'),
+  ("deg", '\u{b0}'),
+  ("delta", '\u{3b4}'),
+  ("diams", '\u{2666}'),
+  ("divide", '\u{f7}'),
+  ("eacute", '\u{e9}'),
+  ("ecirc", '\u{ea}'),
+  ("egrave", '\u{e8}'),
+  ("empty", '\u{2205}'),
+  ("emsp", '\u{2003}'),
+  ("ensp", '\u{2002}'),
+  ("epsilon", '\u{3b5}'),
+  ("equiv", '\u{2261}'),
+  ("eta", '\u{3b7}'),
+  ("eth", '\u{f0}'),
+  ("euml", '\u{eb}'),
+  ("euro", '\u{20ac}'),
+  ("exist", '\u{2203}'),
+  ("fnof", '\u{192}'),
+  ("forall", '\u{2200}'),
+  ("frac12", '\u{bd}'),
+  ("frac14", '\u{bc}'),
+  ("frac34", '\u{be}'),
+  ("frasl", '\u{2044}'),
+  ("gamma", '\u{3b3}'),
+  ("ge", '\u{2265}'),
+  ("gt", '\u{3e}'),
+  ("hArr", '\u{21d4}'),
+  ("harr", '\u{2194}'),
+  ("hearts", '\u{2665}'),
+  ("hellip", '\u{2026}'),
+  ("iacute", '\u{ed}'),
+  ("icirc", '\u{ee}'),
+  ("iexcl", '\u{a1}'),
+  ("igrave", '\u{ec}'),
+  ("image", '\u{2111}'),
+  ("infin", '\u{221e}'),
+  ("int", '\u{222b}'),
+  ("iota", '\u{3b9}'),
+  ("iquest", '\u{bf}'),
+  ("isin", '\u{2208}'),
+  ("iuml", '\u{ef}'),
+  ("kappa", '\u{3ba}'),
+  ("lArr", '\u{21d0}'),
+  ("lambda", '\u{3bb}'),
+  ("lang", '\u{2329}'),
+  ("laquo", '\u{ab}'),
+  ("larr", '\u{2190}'),
+  ("lceil", '\u{2308}'),
+  ("ldquo", '\u{201c}'),
+  ("le", '\u{2264}'),
+  ("lfloor", '\u{230a}'),
+  ("lowast", '\u{2217}'),
+  ("loz", '\u{25ca}'),
+  ("lrm", '\u{200e}'),
+  ("lsaquo", '\u{2039}'),
+  ("lsquo", '\u{2018}'),
+  ("lt", '\u{3c}'),
+  ("macr", '\u{af}'),
+  ("mdash", '\u{2014}'),
+  ("micro", '\u{b5}'),
+  ("middot", '\u{b7}'),
+  ("minus", '\u{2212}'),
+  ("mu", '\u{3bc}'),
+  ("nabla", '\u{2207}'),
+  ("nbsp", '\u{a0}'),
+  ("ndash", '\u{2013}'),
+  ("ne", '\u{2260}'),
+  ("ni", '\u{220b}'),
+  ("not", '\u{ac}'),
+  ("notin", '\u{2209}'),
+  ("nsub", '\u{2284}'),
+  ("ntilde", '\u{f1}'),
+  ("nu", '\u{3bd}'),
+  ("oacute", '\u{f3}'),
+  ("ocirc", '\u{f4}'),
+  ("oelig", '\u{153}'),
+  ("ograve", '\u{f2}'),
+  ("oline", '\u{203e}'),
+  ("omega", '\u{3c9}'),
+  ("omicron", '\u{3bf}'),
+  ("oplus", '\u{2295}'),
+  ("or", '\u{2228}'),
+  ("ordf", '\u{aa}'),
+  ("ordm", '\u{ba}'),
+  ("oslash", '\u{f8}'),
+  ("otilde", '\u{f5}'),
+  ("otimes", '\u{2297}'),
+  ("ouml", '\u{f6}'),
+  ("para", '\u{b6}'),
+  ("part", '\u{2202}'),
+  ("permil", '\u{2030}'),
+  ("perp", '\u{22a5}'),
+  ("phi", '\u{3c6}'),
+  ("pi", '\u{3c0}'),
+  ("piv", '\u{3d6}'),
+  ("plusmn", '\u{b1}'),
+  ("pound", '\u{a3}'),
+  ("prime", '\u{2032}'),
+  ("prod", '\u{220f}'),
+  ("prop", '\u{221d}'),
+  ("psi", '\u{3c8}'),
+  ("quot", '\u{22}'),
+  ("rArr", '\u{21d2}'),
+  ("radic", '\u{221a}'),
+  ("rang", '\u{232a}'),
+  ("raquo", '\u{bb}'),
+  ("rarr", '\u{2192}'),
+  ("rceil", '\u{2309}'),
+  ("rdquo", '\u{201d}'),
+  ("real", '\u{211c}'),
+  ("reg", '\u{ae}'),
+  ("rfloor", '\u{230b}'),
+  ("rho", '\u{3c1}'),
+  ("rlm", '\u{200f}'),
+  ("rsaquo", '\u{203a}'),
+  ("rsquo", '\u{2019}'),
+  ("sbquo", '\u{201a}'),
+  ("scaron", '\u{161}'),
+  ("sdot", '\u{22c5}'),
+  ("sect", '\u{a7}'),
+  ("shy", '\u{ad}'),
+  ("sigma", '\u{3c3}'),
+  ("sigmaf", '\u{3c2}'),
+  ("sim", '\u{223c}'),
+  ("spades", '\u{2660}'),
+  ("sub", '\u{2282}'),
+  ("sube", '\u{2286}'),
+  ("sum", '\u{2211}'),
+  ("sup", '\u{2283}'),
+  ("sup1", '\u{b9}'),
+  ("sup2", '\u{b2}'),
+  ("sup3", '\u{b3}'),
+  ("supe", '\u{2287}'),
+  ("szlig", '\u{df}'),
+  ("tau", '\u{3c4}'),
+  ("there4", '\u{2234}'),
+  ("theta", '\u{3b8}'),
+  ("thetasym", '\u{3d1}'),
+  ("thinsp", '\u{2009}'),
+  ("thorn", '\u{fe}'),
+  ("tilde", '\u{2dc}'),
+  ("times", '\u{d7}'),
+  ("trade", '\u{2122}'),
+  ("uArr", '\u{21d1}'),
+  ("uacute", '\u{fa}'),
+  ("uarr", '\u{2191}'),
+  ("ucirc", '\u{fb}'),
+  ("ugrave", '\u{f9}'),
+  ("uml", '\u{a8}'),
+  ("upsih", '\u{3d2}'),
+  ("upsilon", '\u{3c5}'),
+  ("uuml", '\u{fc}'),
+  ("weierp", '\u{2118}'),
+  ("xi", '\u{3be}'),
+  ("yacute", '\u{fd}'),
+  ("yen", '\u{a5}'),
+  ("yuml", '\u{ff}'),
+  ("zeta", '\u{3b6}'),
+  ("zwj", '\u{200d}'),
+  ("zwnj", '\u{200c}'),
+];
+
+// Entities the game writes without the trailing semicolon.
+const LEGACY_ENTITIES: [(&str, char); 5] = [
+  ("amp", '&'),
+  ("gt", '>'),
+  ("lt", '<'),
+  ("nbsp", ' '),
+  ("quot", '"'),
+];
+
+pub fn decode(body: &str) -> String {
+  let mut text = String::with_capacity(body.len());
+  let mut rest = body;
+  while let Some(i) = rest.find(['<', '&']) {
+    text.push_str(&rest[..i]);
+    rest = &rest[i..];
+    if rest.starts_with('<') {
+      match rest.find('>') {
+        Some(end) if is_tag(&rest[1..end]) => rest = &rest[end + 1..],
+        _ => {
+          text.push('<');
+          rest = &rest[1..];
+        }
+      }
+    } else {
+      match decode_entity(&rest[1..]) {
+        Some((c, len)) => {
+          text.push(c);
+          rest = &rest[1 + len..];
+        }
+        _ => {
+          text.push('&');
+          rest = &rest[1..];
+        }
+      }
+    }
+  }
+  text.push_str(rest);
+  text
+}
+
+// Only the tags the game itself writes, so players' own angle brackets are kept.
+const TAGS: [&str; 2] = ["font", "br"];
+
+fn is_tag(inner: &str) -> bool {
+  let inner = inner.strip_prefix('/').unwrap_or(inner);
+  TAGS.iter().any(|tag| match inner.get(..tag.len()) {
+    Some(name) if name.eq_ignore_ascii_case(tag) => {
+      let rest = &inner[tag.len()..];
+      rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_whitespace() || c == '/')
+    }
+    _ => false,
+  })
+}
+
+fn decode_entity(rest: &str) -> Option<(char, usize)> {
+  if let Some(number) = rest.strip_prefix('#') {
+    let (digits, radix, prefix) = match number.strip_prefix(['x', 'X']) {
+      Some(hex) => (hex, 16, 2),
+      _ => (number, 10, 1),
+    };
+    let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+    if len == 0 {
+      return None;
+    }
+    let code = u32::from_str_radix(&digits[..len], radix).ok()?;
+    let c = match char::from_u32(code) {
+      Some('\0') | None => '\u{fffd}',
+      Some(c) => c,
+    };
+    let semicolon = digits[len..].starts_with(';') as usize;
+    return Some((if c == '\u{a0}' { ' ' } else { c }, prefix + len + semicolon));
+  }
+  let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+  let name = &rest[..len];
+  if rest[len..].starts_with(';') {
+    if let Ok(i) = ENTITIES.binary_search_by(|(entity, _)| entity.cmp(&name)) {
+      let c = ENTITIES[i].1;
+      return Some((if c == '\u{a0}' { ' ' } else { c }, len + 1));
+    }
+  }
+  LEGACY_ENTITIES.iter()
+    .find(|(entity, _)| name.starts_with(entity))
+    .map(|(entity, c)| (*c, entity.len()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_entities() {
+    assert_eq!(decode("JADEN : a&nbspb&nbsp;c"), "JADEN : a b c");
+    assert_eq!(decode("&amp;&lt;&gt;&quot;&apos;"), "&<>\"'");
+    assert_eq!(decode("&copy;&hellip;&eacute;&Omega;"), "©…éΩ");
+    assert_eq!(decode("&#65;&#x42;&#X43;&#12354;&#160;"), "ABCあ ");
+    assert_eq!(decode("&#0;&#xffffffff;"), "\u{fffd}\u{fffd}");
+    assert_eq!(decode("&amp&lt&gt"), "&<>");
+    assert_eq!(decode("& &; &unknown; &#; &#x;"), "& &; &unknown; &#; &#x;");
+  }
+
+  #[test]
+  fn strip_markup() {
+    assert_eq!(decode("<font color=\"#ff0000\">色</font>と<FONT>大文字</FONT>"), "色と大文字");
+    assert_eq!(decode("1 < 2 > 0"), "1 < 2 > 0");
+    assert_eq!(decode("&lt;b&gt;"), "<b>");
+    assert_eq!(decode("a<br>b</br>c<br/>"), "abc");
+  }
+
+  #[test]
+  fn keep_angle_brackets() {
+    assert_eq!(decode("<PT募集>"), "<PT募集>");
+    assert_eq!(decode("a<b and c>d"), "a<b and c>d");
+    assert_eq!(decode("<Lv200>"), "<Lv200>");
+    assert_eq!(decode("<b>太字</b>"), "<b>太字</b>");
+    assert_eq!(decode("<brother><fonts>"), "<brother><fonts>");
+  }
+
+  #[test]
+  fn decode_fixtures() {
+    for name in ["TWChatLog_2024_04_19_large.html", "TWChatLog_2024_04_20.html"] {
+      let bytes = std::fs::read(crate::testing::fixture(name)).unwrap();
+      let (text, _, _) = encoding_rs::SHIFT_JIS.decode(&bytes);
+      for line in text.split("\r\n") {
+        if let Some(captures) = crate::line::LINE.captures(line) {
+          assert_eq!(decode(&captures[3]), &captures[3]);
+        }
+      }
+    }
+  }
+}
@@ -1,8 +1,56 @@
-use std::{fs::{self, File}, io::{Read, Seek, SeekFrom}, path::Path};
+use std::{collections::{BTreeMap, VecDeque}, path::{Path, PathBuf}};
+use once_cell::sync::Lazy;
 use regex::Regex;
 use chrono_tz::Asia::Tokyo;
-use chrono::{DateTime, Datelike, Duration, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
 use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+mod archive;
+mod clock;
+mod config;
+mod header;
+mod history;
+mod html;
+mod line;
+mod log_dir;
+mod message;
+mod replay;
+mod source;
+mod store;
+mod speaker;
+mod tail;
+#[cfg(test)]
+mod testing;
+mod watch;
+
+pub use archive::{Archive, ArchiveEntry};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::{ChannelConfig, ColorMap};
+pub use header::LogHeader;
+pub use history::{list_logs, LogFileInfo};
+pub use line::{RawLine, Reassembler};
+pub use log_dir::{discover_log_dirs, log_file_date, log_file_path, validate_log_dir, DEFAULT_LOG_DIR};
+pub use message::{Channel, ChannelLog, ChatMessage, ExpSummary, DEFAULT_CAPACITY, Quarantined, QuarantineReport, Whisper, WhisperDirection, WhisperPartner};
+pub use replay::{ReplayClock, ReplayLogSource, ReplaySpeed};
+pub use source::{FsLogSource, LogSource, MemoryLogSource, SourceConfig, SourceIdentity};
+pub use store::MessageStore;
+pub use tail::TailReader;
+pub use watch::LogWatcher;
+
+const QUARANTINE_LIMIT: usize = 1000;
+const ARCHIVE_BACKLOG: usize = 10000;
+const DISPLAY_LIMIT: usize = 500;
+
+static EXP: Lazy<Regex> = Lazy::new(|| Regex::new(r##"経験値が (\d+) 上がりました。"##).unwrap());
+
+// A wrapped line held at the end of a file is let go if nothing follows it
+// for this long.
+const WRAP_FLUSH_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+// A replay releases lines by its clock rather than by file changes.
+const REPLAY_TICK: std::time::Duration = std::time::Duration::from_secs(1);
+
+pub const PRIMARY_SOURCE: &str = "main";
 
 #[derive(Debug)]
 pub struct App {
@@ -12,155 +60,760 @@ pub struct App {
   pub verbose: bool,
   pub vertical: bool,
   pub limit: bool,
-  pub messages: Vec<(Vec<(String, String, String)>, bool)>,
-  file: Option<File>,
+  pub parse_mode: ParseMode,
+  merge_wrapped: bool,
+  pub backfill: Backfill,
+  pub source_filter: Option<String>,
+  messages: MessageStore,
+  color_map: ColorMap,
+  quarantine: Vec<Quarantined>,
+  quarantined: u64,
+  clock: Box<dyn Clock>,
+  tails: Vec<LogTail>,
+  archive: Option<Archive>,
+  archived: Vec<ArchiveEntry>,
+  archive_errors: Vec<String>,
+  archive_failing: bool,
+  archive_lost: usize,
+  source_errors: Vec<String>,
+  replay: Option<ReplayClock>,
+  seq: u64,
+}
+
+// Tailing state of one log directory, e.g. one game client's install.
+#[derive(Debug)]
+struct LogTail {
+  name: String,
+  log_dir: PathBuf,
+  source: Box<dyn LogSource>,
+  identity: Option<SourceIdentity>,
+  late: bool,
+  backfilling: bool,
+  backfill_since: Option<DateTime<Utc>>,
   file_size: u64,
-  date: NaiveDateTime,
+  day: NaiveDate,
+  header: Option<LogHeader>,
+  header_end: u64,
+  last_time: Option<DateTime<FixedOffset>>,
+  tail: TailReader,
+  wrap: Reassembler,
+  incoming: VecDeque<Incoming>,
+  failing: bool,
+}
+
+// A message read but not numbered yet. Those read from every source in one go
+// are put in the views together, in time order.
+#[derive(Debug)]
+struct Incoming {
+  message: ChatMessage,
+  view: Option<usize>,
+  file: Option<PathBuf>,
+  line: RawLine,
+}
+
+impl LogTail {
+  fn new(name: &str, log_dir: PathBuf, source: Box<dyn LogSource>, day: NaiveDate) -> Self {
+    LogTail {
+      name: name.to_string(),
+      log_dir,
+      source,
+      identity: None,
+      late: false,
+      backfilling: false,
+      backfill_since: None,
+      file_size: 0,
+      day,
+      header: None,
+      header_end: 0,
+      last_time: None,
+      tail: TailReader::new(),
+      wrap: Reassembler::new(),
+      incoming: VecDeque::new(),
+      failing: false,
+    }
+  }
+
+  fn reset(&mut self) {
+    self.source.close();
+    self.identity = None;
+    self.late = false;
+    self.file_size = 0;
+    self.tail.reset();
+  }
+}
+
+impl Default for App {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+  Strict,
+  Tolerant,
+}
+
+// What to load from a log file that already exists when we start tailing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backfill {
+  Off,
+  All,
+  Lines(usize),
+  Minutes(i64),
 }
 
 pub enum ReadStatus {
   Ok,
   Updated,
   Unchanged,
+  Rotated,
+  Truncated,
 }
 
 impl App {
   pub fn new() -> Self {
+    let day = SystemClock.today();
     App {
-      views: vec![true; 7],
+      views: vec![true; Channel::VIEWS.len()],
       exp: false,
       verbose: false,
       vertical: true,
-      auto_scroll: vec![true; 7],
+      auto_scroll: vec![true; Channel::VIEWS.len()],
       limit: true,
-      messages: vec![(Vec::new(), false); 7],
-      file: None,
-      file_size: 0,
-      date: Utc::now().naive_utc(),
+      parse_mode: ParseMode::Tolerant,
+      merge_wrapped: false,
+      backfill: Backfill::Off,
+      source_filter: None,
+      messages: MessageStore::new(Channel::VIEWS.iter().map(|channel| (channel.clone(), DEFAULT_CAPACITY)).collect()),
+      color_map: ColorMap::default(),
+      quarantine: Vec::new(),
+      quarantined: 0,
+      clock: Box::new(SystemClock),
+      tails: vec![LogTail::new(PRIMARY_SOURCE, PathBuf::from(DEFAULT_LOG_DIR), Box::new(FsLogSource::new()), day)],
+      archive: None,
+      archived: Vec::new(),
+      archive_errors: Vec::new(),
+      archive_failing: false,
+      archive_lost: 0,
+      source_errors: Vec::new(),
+      replay: None,
+      seq: 0,
     }
   }
 
-  pub fn read_log(&mut self, path: &Path, date: NaiveDateTime) -> Result<ReadStatus> {
-    if !path.is_file() {
-      return Ok(ReadStatus::Ok);
+  pub fn set_color_map(&mut self, color_map: ColorMap) {
+    let channels: Vec<_> = Channel::VIEWS.iter().chain(color_map.custom_channels().iter()).cloned().collect();
+    self.views.resize(channels.len(), true);
+    self.auto_scroll.resize(channels.len(), true);
+    self.messages = MessageStore::new(channels.into_iter().map(|channel| {
+      let capacity = color_map.capacity(&channel);
+      (channel, capacity)
+    }).collect());
+    self.color_map = color_map;
+  }
+
+  // Lets every view hold all its messages, e.g. to show a whole past log.
+  pub fn set_unbounded(&mut self) {
+    self.messages = MessageStore::new(self.channels().into_iter().map(|channel| (channel, usize::MAX)).collect());
+  }
+
+  pub fn color_map(&self) -> &ColorMap {
+    &self.color_map
+  }
+
+  pub fn channels(&self) -> Vec<Channel> {
+    self.messages.channels()
+  }
+
+  fn view_index(&self, channel: &Channel) -> Option<usize> {
+    self.messages.view_index(channel)
+  }
+
+  pub fn set_source(&mut self, source: Box<dyn LogSource>) {
+    self.tails[0].reset();
+    self.tails[0].source = source;
+  }
+
+  pub fn add_source(&mut self, name: &str, log_dir: PathBuf, source: Box<dyn LogSource>) -> Result<()> {
+    if self.is_replay() {
+      bail!("再生中はソースを変更できません。");
+    }
+    let name = name.trim();
+    if name.is_empty() {
+      bail!("ソース名が空です。");
     }
-    let now = Tokyo.from_utc_datetime(&date);
-    let past = Tokyo.from_utc_datetime(&self.date);
-    self.date = date;
-    if let None = self.file {
-      self.file = Some(File::open(path)?);
-    } else if past.day() != now.day() {
-      drop(self.file.take());
-      self.file = Some(File::open(path)?);
+    if self.tails.iter().any(|tail| tail.name == name) {
+      bail!("ソース \"{}\" はすでにあります。", name);
     }
+    let day = self.clock.today();
+    self.tails.push(LogTail::new(name, log_dir, source, day));
+    Ok(())
+  }
 
-    let file_size = fs::metadata(path)?.len();  
-    if self.file_size == 0 {
-      self.file_size = file_size;
-      self.file.as_ref().unwrap().seek(SeekFrom::Start(file_size))?;
-      return Ok(ReadStatus::Ok);
+  pub fn remove_source(&mut self, name: &str) -> Result<()> {
+    if self.is_replay() {
+      bail!("再生中はソースを変更できません。");
     }
-    if self.file_size == file_size && past.day() == now.day() {
-      return Ok(ReadStatus::Unchanged);
+    match self.tails.iter().position(|tail| tail.name == name) {
+      Some(0) => bail!("ソース \"{}\" は削除できません。", name),
+      Some(t) => {
+        let day = self.tails[t].day;
+        self.flush_wrapped(t, day)?;
+        self.commit()?;
+        self.tails.remove(t);
+        Ok(())
+      }
+      _ => bail!("ソース \"{}\" はありません。", name),
     }
-    let buf_size = if past.day() == now.day() {
-      file_size - self.file_size
-    } else {
-      file_size
+  }
+
+  pub fn sources(&self) -> Vec<SourceConfig> {
+    self.tails.iter().map(|tail| SourceConfig { name: tail.name.clone(), log_dir: tail.log_dir.clone() }).collect()
+  }
+
+  pub fn set_archive(&mut self, archive: Option<Archive>) {
+    self.archive = archive;
+    self.archived.clear();
+    self.archive_failing = false;
+  }
+
+  pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+    for tail in self.tails.iter_mut() {
+      tail.day = clock.today();
+    }
+    self.clock = clock;
+  }
+
+  // Plays back a finished log file through the same tail as a live one. It is
+  // read from its start like a file created after we started watching.
+  pub fn replay(&mut self, source: ReplayLogSource) {
+    self.set_clock(Box::new(source.clock()));
+    self.replay = Some(source.clock());
+    self.set_source(Box::new(source));
+    self.tails[0].late = true;
+  }
+
+  // A replay App stands in for the live one, so the sources and the log
+  // folder it has are not the user's and cannot be changed.
+  pub fn is_replay(&self) -> bool {
+    self.replay.is_some()
+  }
+
+  // How long the tailer can wait for a file change before it has to read
+  // anyway, or None to wait for the next change however long it takes.
+  pub fn next_wake(&self) -> Option<std::time::Duration> {
+    let held = match self.tails.iter().any(|tail| !tail.wrap.is_empty()) {
+      true => Some(WRAP_FLUSH_DELAY),
+      false => None,
+    };
+    let due = match &self.replay {
+      Some(replay) if replay.is_finished() => None,
+      Some(_) => Some(REPLAY_TICK),
+      _ => self.until_midnight(),
     };
-    self.file_size = file_size;
-    let mut content = vec![0; buf_size as usize];
-    self.file.as_ref().unwrap().read(&mut content)?;
-    let (cow, _, _) = encoding_rs::SHIFT_JIS.decode(&content);
-    let message = cow.into_owned();
-
-    let mut messages: Vec<_> = message.split("\r\n").filter(|e| e.trim() != "").collect();
-    if past.day() != now.day() {
-      for _ in 0..4 {
-        messages.remove(0);
+    [held, due].into_iter().flatten().min()
+  }
+
+  // Today's file is left for the next day's at midnight on the server.
+  fn until_midnight(&self) -> Option<std::time::Duration> {
+    let midnight = self.clock.today().succ_opt()?.and_hms_opt(0, 0, 0)?;
+    let midnight = Tokyo.from_local_datetime(&midnight).single()?.with_timezone(&Utc);
+    (midnight - self.clock.now()).to_std().ok()
+  }
+
+  pub fn log_dir(&self) -> &Path {
+    &self.tails[0].log_dir
+  }
+
+  pub fn set_log_dir(&mut self, dir: PathBuf) -> Result<()> {
+    if self.is_replay() {
+      bail!("再生中はログフォルダを変更できません。");
+    }
+    let day = self.tails[0].day;
+    self.flush_wrapped(0, day)?;
+    self.commit()?;
+    self.tails[0].reset();
+    self.tails[0].log_dir = dir;
+    Ok(())
+  }
+
+  pub fn log_path(&self) -> PathBuf {
+    log_file_path(self.log_dir(), self.clock.today())
+  }
+
+  pub fn read_log(&mut self, path: &Path) -> Result<ReadStatus> {
+    self.clear_updated();
+    let status = self.read_tail(0, path)?;
+    self.commit()?;
+    self.flush_archive();
+    Ok(status)
+  }
+
+  // Reads today's file of every source. Messages from all of them go to the
+  // same views, merged by time.
+  pub fn read_logs(&mut self) -> Result<ReadStatus> {
+    self.clear_updated();
+    let mut result = ReadStatus::Unchanged;
+    for t in 0..self.tails.len() {
+      let path = log_file_path(&self.tails[t].log_dir, self.clock.today());
+      // A source that cannot be read is reported once and tried again on the
+      // next read, without holding up the others.
+      let status = match self.read_tail(t, &path) {
+        Ok(status) => {
+          self.tails[t].failing = false;
+          status
+        }
+        Err(e) => {
+          if !self.tails[t].failing {
+            self.source_errors.push(format!("{}: {:#}", self.tails[t].name, e));
+          }
+          self.tails[t].failing = true;
+          continue;
+        }
+      };
+      match status {
+        ReadStatus::Unchanged => (),
+        ReadStatus::Ok => {
+          if let ReadStatus::Unchanged = result {
+            result = ReadStatus::Ok;
+          }
+        }
+        status => {
+          if let ReadStatus::Unchanged | ReadStatus::Ok = result {
+            result = status;
+          }
+        }
       }
     }
-    for i in 0..self.messages.len() {
-      self.messages[i].1 = false;
-    }
-    let regex = Regex::new(r##"^<font.+> (.+) </font> <font.+color="(.+)">(.+)</font></br>$"##).unwrap();
-    for message in messages {
-      match regex.captures(&message) {
-        Some(captures) => {
-          let time = &captures[1];
-          let color = &captures[2];
-          let message = &captures[3].replace("&nbsp", " ");
-          let i = match color {
-            "#c8ffc8" | "#ffffff" => 1,
-            "#64ff64" => 2,
-            "#f7b73c" => 3,
-            "#94ddfa" => 4,
-            "#ff64ff" | "#ff6464" | "#64ff80" => 5,
-            "#c896c8" => 6,
-            _ => bail!("invalid captured color.: {} {} {}", color, time, message),
+    self.commit()?;
+    self.flush_archive();
+    Ok(result)
+  }
+
+  fn read_tail(&mut self, t: usize, path: &Path) -> Result<ReadStatus> {
+    if !self.tails[t].source.exists(path) {
+      // A file that shows up after we started watching is read from its start.
+      self.tails[t].late = true;
+      return Ok(ReadStatus::Ok);
+    }
+    // The day follows the clock rather than the file, so a replay that runs
+    // past midnight keeps reading the same file.
+    let today = self.clock.today();
+    let past = self.tails[t].day;
+    self.tails[t].day = today;
+
+    let identity = self.tails[t].source.identity(path)?;
+    let status = match self.tails[t].identity.clone() {
+      None => {
+        let late = self.tails[t].late;
+        self.open(t, path, identity)?;
+        if !late {
+          if let Backfill::Off = self.backfill {
+            let file_size = self.tails[t].source.size()?;
+            self.tails[t].file_size = file_size;
+            self.tails[t].tail.seek(file_size);
+            return Ok(ReadStatus::Ok);
+          }
+          self.tails[t].backfilling = true;
+          self.tails[t].backfill_since = match self.backfill {
+            Backfill::Minutes(minutes) => Some(self.clock.now() - Duration::minutes(minutes)),
+            _ => None,
           };
-          self.messages[0].0.push(((*message).clone(), color.to_string(), time.to_string()));
-          self.messages[0].1 = true;
-          self.messages[i].0.push(((*message).clone(), color.to_string(), time.to_string()));
-          self.messages[i].1 = true;
         }
-        _ => bail!("regex does not match.: {}", message),
+        ReadStatus::Updated
+      }
+      Some(current) if past != today || !current.same_file(&identity) => {
+        self.flush_wrapped(t, past)?;
+        self.open(t, path, identity)?;
+        ReadStatus::Rotated
+      }
+      Some(_) if self.tails[t].source.size()? < self.tails[t].file_size => {
+        self.flush_wrapped(t, past)?;
+        self.open(t, path, identity)?;
+        ReadStatus::Truncated
       }
+      Some(_) => {
+        self.tails[t].identity = Some(identity);
+        ReadStatus::Updated
+      }
+    };
+    let date = self.tails[t].header.map(|header| header.date).unwrap_or(today);
+
+    let file_size = self.tails[t].source.size()?;
+    let offset = self.tails[t].file_size;
+    if offset == file_size {
+      if let ReadStatus::Updated = status {
+        return match self.flush_wrapped(t, date)? {
+          true => Ok(ReadStatus::Updated),
+          false => Ok(ReadStatus::Unchanged),
+        };
+      }
+      return Ok(status);
+    }
+    let content = self.tails[t].source.read_from(offset, file_size - offset)?;
+    self.tails[t].file_size += content.len() as u64;
+
+    let mut messages = self.tails[t].tail.feed(&content);
+    let backfilling = self.tails[t].backfilling;
+    if backfilling {
+      if let Backfill::Lines(lines) = self.backfill {
+        messages.drain(..messages.len().saturating_sub(lines));
+      }
+    }
+    self.parse_lines(t, messages, date)?;
+    if backfilling {
+      self.flush_wrapped(t, date)?;
+      self.tails[t].backfilling = false;
+      self.tails[t].backfill_since = None;
     }
-    Ok(ReadStatus::Updated)
+    Ok(status)
   }
 
-  pub fn get_messages(&self) -> Vec<(Vec<(String, String, String)>, bool)> {
-    if self.limit {
-      let mut messages = vec![(Vec::new(), false); 7];
-      for i in 0..self.messages.len() {
-        if 500 < self.messages[i].0.len() {
-          messages[i].0 = (&(self.messages[i].0)[self.messages[i].0.len() - 500..self.messages[i].0.len()]).to_vec();
-        } else {
-          messages[i].0 = self.messages[i].0.clone();
+  // Reads a whole finished log file, e.g. a past day's log to browse.
+  pub fn load_log(&mut self, path: &Path) -> Result<()> {
+    let identity = self.tails[0].source.identity(path)?;
+    self.open(0, path, identity)?;
+    let date = match self.tails[0].header.map(|header| header.date) {
+      Some(date) => date,
+      _ => match path.file_name().and_then(|name| name.to_str()).and_then(log_file_date) {
+        Some(date) => date,
+        _ => bail!("{} の日付がわかりません。", path.display()),
+      },
+    };
+    let file_size = self.tails[0].source.size()?;
+    let content = self.tails[0].source.read_from(0, file_size)?;
+    self.tails[0].file_size = content.len() as u64;
+    let mut messages = self.tails[0].tail.feed(&content);
+    let pending = self.tails[0].tail.pending();
+    if !pending.trim().is_empty() {
+      messages.push((self.tails[0].tail.offset(), pending));
+    }
+    self.parse_lines(0, messages, date)?;
+    self.flush_wrapped(0, date)?;
+    self.commit()?;
+    self.flush_archive();
+    Ok(())
+  }
+
+  // What was read since the last flush goes to the archive in one batch. A
+  // batch that fails, e.g. on a locked database, is tried again with the next
+  // one and never fails the read. Only the latest ARCHIVE_BACKLOG messages
+  // wait for it.
+  fn flush_archive(&mut self) {
+    let archive = match self.archive.as_mut() {
+      Some(archive) => archive,
+      _ => return,
+    };
+    match archive.insert(&self.archived) {
+      Ok(_) => {
+        self.archived.clear();
+        self.archive_failing = false;
+        if 0 < self.archive_lost {
+          self.archive_errors.push(format!("書き込めなかった {} 件のメッセージはアーカイブされていません。", self.archive_lost));
+          self.archive_lost = 0;
+        }
+      }
+      Err(e) => {
+        if !self.archive_failing {
+          self.archive_errors.push(format!("アーカイブに書き込めません。書き込めるようになるまで再試行します。\n{:#}", e));
+        }
+        self.archive_failing = true;
+        let lost = self.archived.len().saturating_sub(ARCHIVE_BACKLOG);
+        self.archived.drain(..lost);
+        self.archive_lost += lost;
+      }
+    }
+  }
+
+  // The errors of failed archive writes, once until writes succeed again,
+  // and then how many messages were lost meanwhile.
+  pub fn take_archive_errors(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.archive_errors)
+  }
+
+  // The errors of sources that could not be read, once until they are read again.
+  pub fn take_source_errors(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.source_errors)
+  }
+
+  pub fn is_failing(&self) -> bool {
+    self.tails.iter().any(|tail| tail.failing)
+  }
+
+  fn flush_wrapped(&mut self, t: usize, date: NaiveDate) -> Result<bool> {
+    match self.tails[t].wrap.flush() {
+      Some(line) => {
+        let date = self.tails[t].header.map(|header| header.date).unwrap_or(date);
+        self.push_line(t, line, date)?;
+        Ok(true)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  fn clear_updated(&mut self) {
+    self.messages.clear_updated();
+  }
+
+  pub fn merge_wrapped(&self) -> bool {
+    self.merge_wrapped
+  }
+
+  // A wrapped line held for merging is let go before merging stops, so it is
+  // not put after the lines read next.
+  pub fn set_merge_wrapped(&mut self, merge_wrapped: bool) -> Result<()> {
+    if self.merge_wrapped && !merge_wrapped {
+      for t in 0..self.tails.len() {
+        let day = self.tails[t].day;
+        self.flush_wrapped(t, day)?;
+      }
+      self.commit()?;
+      self.flush_archive();
+    }
+    self.merge_wrapped = merge_wrapped;
+    Ok(())
+  }
+
+  fn open(&mut self, t: usize, path: &Path, identity: SourceIdentity) -> Result<()> {
+    let tail = &mut self.tails[t];
+    tail.source.open(path)?;
+    let content = tail.source.read_from(0, header::HEADER_SIZE)?;
+    tail.header = LogHeader::read(&content);
+    tail.header_end = header::header_end(&content);
+    tail.identity = Some(identity);
+    tail.late = false;
+    tail.last_time = None;
+    tail.file_size = 0;
+    tail.tail.reset();
+    Ok(())
+  }
+
+  fn parse_lines(&mut self, t: usize, messages: Vec<(u64, String)>, date: NaiveDate) -> Result<()> {
+    for (offset, line) in messages {
+      // Only the header block is skipped, so a chat line that looks like a
+      // header line is still read.
+      if offset < self.tails[t].header_end {
+        continue;
+      }
+      let line = match RawLine::parse(&line) {
+        Some(line) => RawLine { offset, ..line },
+        _ => {
+          self.quarantine(&line, format!("regex does not match.: {}", line), None)?;
+          continue;
+        }
+      };
+      if !self.merge_wrapped {
+        self.push_line(t, line, date)?;
+      } else if let Some(line) = self.tails[t].wrap.push(line) {
+        self.push_line(t, line, date)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn push_line(&mut self, t: usize, line: RawLine, date: NaiveDate) -> Result<()> {
+    let text = html::decode(&line.body);
+    let time = match line.time_of_day() {
+      Some(time) => time,
+      _ => return self.quarantine(&line.raw, format!("invalid captured time.: {} {} {}", line.color, line.time, text), None),
+    };
+    let channel = self.color_map.channel(&line.color);
+    let view = self.view_index(&channel);
+    let time = self.timestamp(t, date, time);
+    if let Some(since) = self.tails[t].backfill_since {
+      if time < since {
+        return Ok(());
+      }
+    }
+    let (speaker, text) = match speaker::split_speaker(&channel, &text) {
+      Some((speaker, text)) => (Some(speaker), text),
+      _ => (None, text),
+    };
+    let (speaker, whisper) = match speaker {
+      Some(speaker) if channel == Channel::Whisper => match speaker::whisper(&speaker) {
+        whisper if whisper.direction == WhisperDirection::Outgoing => (None, Some(whisper)),
+        whisper => (Some(speaker), Some(whisper)),
+      },
+      speaker => (speaker, None),
+    };
+    let tail = &mut self.tails[t];
+    let message = ChatMessage { seq: 0, source: tail.name.clone(), speaker, whisper, text, color: line.color.clone(), channel, time, backfilled: tail.backfilling };
+    let file = tail.identity.as_ref().map(|identity| identity.path.clone());
+    tail.incoming.push_back(Incoming { message, view, file, line });
+    Ok(())
+  }
+
+  // Numbers the messages read from every source and puts them in the views,
+  // the earliest first. Each source's own lines keep their order.
+  fn commit(&mut self) -> Result<()> {
+    loop {
+      let next = self.tails.iter().enumerate().filter_map(|(t, tail)| tail.incoming.front().map(|incoming| (incoming.message.time, t))).min();
+      let t = match next {
+        Some((_, t)) => t,
+        _ => return Ok(()),
+      };
+      let Incoming { mut message, view, file, line } = self.tails[t].incoming.pop_front().unwrap();
+      message.seq = self.seq;
+      self.seq += 1;
+      // A message of an unknown color is archived too, so it is there once
+      // the color is given a channel.
+      if let (Some(_), Some(file)) = (&self.archive, file) {
+        self.archived.push(ArchiveEntry { file, offset: line.offset, raw: line.raw.clone(), message: message.clone() });
+      }
+      match view {
+        Some(i) => {
+          self.messages.push(message, i);
+        }
+        _ => {
+          let reason = format!("invalid captured color.: {} {} {}", message.color, message.time, message.text);
+          self.quarantine(&line.raw, reason, Some(message))?;
+        }
+      }
+    }
+  }
+
+  // Lines are only stamped with a time of day, so a line that goes backwards by
+  // more than half a day is taken to have crossed midnight.
+  fn timestamp(&mut self, t: usize, date: NaiveDate, time: NaiveTime) -> DateTime<FixedOffset> {
+    let mut timestamp = jst(date, time);
+    if let Some(last_time) = self.tails[t].last_time {
+      while timestamp < last_time - Duration::hours(12) {
+        timestamp += Duration::days(1);
+      }
+    }
+    self.tails[t].last_time = Some(timestamp);
+    timestamp
+  }
+
+  fn quarantine(&mut self, raw: &str, reason: String, message: Option<ChatMessage>) -> Result<()> {
+    if let ParseMode::Strict = self.parse_mode {
+      bail!(reason);
+    }
+    self.quarantined += 1;
+    if QUARANTINE_LIMIT <= self.quarantine.len() {
+      self.quarantine.remove(0);
+    }
+    self.quarantine.push(Quarantined { raw: raw.to_string(), reason, message });
+    Ok(())
+  }
+
+  pub fn get_quarantine(&self) -> QuarantineReport {
+    QuarantineReport { count: self.quarantined, lines: self.quarantine.clone() }
+  }
+
+  pub fn whisper_partners(&self) -> Vec<WhisperPartner> {
+    let mut partners: Vec<WhisperPartner> = Vec::new();
+    let whisper = self.view_index(&Channel::Whisper).unwrap();
+    for message in self.messages.iter(whisper).rev() {
+      if let Some(whisper) = &message.whisper {
+        if partners.iter().all(|partner| partner.name != whisper.partner) {
+          partners.push(WhisperPartner {
+            name: whisper.partner.clone(),
+            last_time: message.time,
+            last_direction: whisper.direction,
+          });
+        }
+      }
+    }
+    partners
+  }
+
+  fn is_shown(&self, message: &ChatMessage) -> bool {
+    self.source_filter.as_ref().map(|source| &message.source == source).unwrap_or(true)
+  }
+
+  pub fn display_limit(&self) -> Option<usize> {
+    match self.limit {
+      true => Some(DISPLAY_LIMIT),
+      false => None,
+    }
+  }
+
+  pub fn get_messages(&self) -> Vec<ChannelLog> {
+    self.messages.logs(|message| self.is_shown(message), self.display_limit().unwrap_or(usize::MAX))
+  }
+
+  // A page of a channel's messages read before `before_seq`, or the latest
+  // ones, oldest first.
+  pub fn get_messages_range(&self, channel: &Channel, before_seq: Option<u64>, limit: usize) -> Vec<ChatMessage> {
+    let view = match self.view_index(channel) {
+      Some(view) => view,
+      _ => return Vec::new(),
+    };
+    let mut messages: Vec<_> = self.messages.before(view, before_seq).rev().filter(|message| self.is_shown(message)).take(limit).cloned().collect();
+    messages.reverse();
+    messages
+  }
+
+  pub fn last_seq(&self) -> Option<u64> {
+    self.seq.checked_sub(1)
+  }
+
+  // Messages read after `seq` in the order they were read. With a display
+  // limit, only those still among the latest of a view, as in get_messages.
+  pub fn messages_after(&self, seq: Option<u64>) -> Vec<ChatMessage> {
+    let limit = match self.display_limit() {
+      Some(limit) => limit,
+      _ => return self.messages.after(seq).filter(|message| self.is_shown(message)).cloned().collect(),
+    };
+    let mut shown = BTreeMap::new();
+    for view in 0..self.messages.channels().len() {
+      for message in self.messages.iter(view).rev().filter(|message| self.is_shown(message)).take(limit) {
+        if seq.map(|seq| seq < message.seq).unwrap_or(true) {
+          shown.insert(message.seq, message);
         }
-        messages[i].1 = self.messages[i].1;
       }
-      messages
-    } else {
-      self.messages.clone()
     }
+    shown.into_values().cloned().collect()
   }
 
-  pub fn calc_exp(&self, now: NaiveDateTime) -> (i64, i64, i64) {
-    let mut total_exp = 0 as i64;
+  pub fn search(&self, query: &str, channel: &Channel) -> Vec<ChatMessage> {
+    let query = query.to_lowercase();
+    let view = match self.view_index(channel) {
+      Some(view) => view,
+      _ => return Vec::new(),
+    };
+    self.messages.iter(view).filter(|message| {
+      message.text.to_lowercase().contains(&query)
+        || message.speaker.as_ref().map(|speaker| speaker.to_lowercase().contains(&query)).unwrap_or(false)
+    }).cloned().collect()
+  }
+
+  pub fn exp_summary(&self) -> ExpSummary {
+    let system = self.view_index(&Channel::System).unwrap();
+    let mut summary = ExpSummary { total: 0, count: 0, first_time: None, last_time: None, per_hour: 0 };
+    for message in self.messages.iter(system) {
+      if let Some(captures) = EXP.captures(&message.text) {
+        summary.total += captures[1].parse::<i64>().unwrap();
+        summary.count += 1;
+        summary.first_time = summary.first_time.or(Some(message.time));
+        summary.last_time = Some(message.time);
+      }
+    }
+    if let (Some(first), Some(last)) = (summary.first_time, summary.last_time) {
+      let seconds = (last - first).num_seconds();
+      if 0 < seconds {
+        summary.per_hour = summary.total * 60 * 60 / seconds;
+      }
+    }
+    summary
+  }
+
+  pub fn calc_exp(&self) -> (i64, i64, i64) {
+    let mut total_exp = 0;
     let span = 3;
-    for i in (0..self.messages[5].0.len()).rev() {
-      let message = &self.messages[5].0[i];
-      let regex = Regex::new(r##"経験値が (\d+) 上がりました。"##).unwrap();
-      match regex.captures(&message.0) {
-        Some(captures) => {
-          let regex = Regex::new(r##"\[\s?(\d+)時\s+(\d+)分\s+(\d+)秒\]"##).unwrap();
-          match regex.captures(&message.2) {
-            Some(caps) => {
-              let hour = &caps[1];
-              let minute = &caps[2];
-              let second = &caps[3];
-              let now = Tokyo.from_utc_datetime(&now);
-              let now = DateTime::parse_from_str(format!("{}{:0>2}{:0>2}{:0>2}{:0>2}{:0>2} +0900", now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second()).as_str(), "%Y%m%d%H%M%S %z").unwrap();
-              let time = DateTime::parse_from_str(format!("{}{:0>2}{:0>2}{:0>2}{:0>2}{:0>2} +0900", now.year(), now.month(), now.day(), hour, minute, second).as_str(), "%Y%m%d%H%M%S %z").unwrap();
-              let end = now - Duration::seconds(span);
-              let time = if now.day() != end.day() && time.hour() == 23 {
-                time - Duration::days(1)
-              } else {
-                time
-              };
-              if end <= time {
-                let exp = (&captures[1]).parse::<i64>().unwrap();
-                total_exp += exp;
-              } else {
-                break;
-              }
-            }
-            _ => (),
-          }
+    let end = self.clock.now() - Duration::seconds(span);
+    let system = self.view_index(&Channel::System).unwrap();
+    for message in self.messages.iter(system).rev().filter(|message| self.is_shown(message)) {
+      if let Some(captures) = EXP.captures(&message.text) {
+        if end <= message.time {
+          let exp = captures[1].parse::<i64>().unwrap();
+          total_exp += exp;
+        } else {
+          break;
         }
-        _ => (),
       }
     }
     let exp_per_second = total_exp / span;
@@ -170,245 +823,776 @@ impl App {
   }
 }
 
+fn jst(date: NaiveDate, time: NaiveTime) -> DateTime<FixedOffset> {
+  Tokyo.from_local_datetime(&date.and_time(time)).unwrap().fixed_offset()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::testing::*;
+
+  fn manual_app() -> (App, ManualClock) {
+    let mut app = App::new();
+    let clock = ManualClock::new(utc("2000/01/01 00:00:00"));
+    app.set_clock(Box::new(clock.clone()));
+    (app, clock)
+  }
+
+  fn read_past_file(app: &mut App, clock: &ManualClock, name: &str, date: &str) {
+    clock.set(utc(date));
+    assert!(matches!(app.read_log(&fixture(name)).unwrap(), ReadStatus::Ok));
+  }
+
+  fn system_messages_2024_04_20() -> Vec<ChatMessage> {
+    vec![
+      message(0, "◇本日の毎日課題：ステッドを退治", "#ff64ff", Channel::System, (0, 0, 0)),
+      message(1, "◇本日の毎日課題：アビス深層96階以上クリア", "#ff64ff", Channel::System, (0, 0, 0)),
+      message(2, "◇本日の毎日課題：ピリ辛ナテスコ煮", "#ff64ff", Channel::System, (0, 0, 0)),
+      message(3, "?フォレスト?が1分後に「ルーンの庭園」を訪問します。", "#ff64ff", Channel::System, (0, 0, 0)),
+      message(4, "ルーンの庭園へ訪れると、妖精や精霊たちが嬉しそうに迎えてくれます。", "#ff64ff", Channel::System, (0, 0, 0)),
+      message(5, "プシーキーの迷宮が再設定されました。", "#ff64ff", Channel::System, (0, 0, 1)),
+      message(6, "プラバ防衛戦が始まりました。", "#ff64ff", Channel::System, (0, 0, 1)),
+      message(7, "[チーム経験値アップイベント] 始まりました！", "#ff64ff", Channel::System, (0, 0, 1)),
+      message(8, "ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ", "#ff64ff", Channel::System, (0, 0, 2)),
+      message(9, "い。", "#ff64ff", Channel::System, (0, 0, 2)),
+      message(10, "[チーム経験値アップイベント] 実施中です！", "#ff64ff", Channel::System, (0, 0, 59)),
+    ]
+  }
+
+  fn channel_logs(system: Vec<ChatMessage>) -> Vec<ChannelLog> {
+    let mut logs: Vec<_> = Channel::VIEWS.iter().map(|channel| ChannelLog::new(channel.clone())).collect();
+    if !system.is_empty() {
+      logs[0].messages = system.clone().into();
+      logs[0].updated = true;
+      logs[5].messages = system.into();
+      logs[5].updated = true;
+    }
+    logs
+  }
 
   #[test]
   fn read_file_larger_past_file() {
-    let mut app = App::new();
-    let path ="test\\TWChatLog_2024_04_20.html";
-    let path = Path::new(path);
-    app.file = Some(File::open(path).unwrap());
-    app.file_size = std::fs::metadata("test\\TWChatLog_2024_04_19_large.html").unwrap().len();
-    app.date = NaiveDateTime::parse_from_str("2024/04/19 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    let date = NaiveDateTime::parse_from_str("2024/04/20 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    app.read_log(path, date).unwrap();
-    assert_eq!(app.messages,
-      [
-        (
-          vec![
-            (String::from("◇本日の毎日課題：ステッドを退治"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：アビス深層96階以上クリア"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：ピリ辛ナテスコ煮"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("?フォレスト?が1分後に「ルーンの庭園」を訪問します。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("ルーンの庭園へ訪れると、妖精や精霊たちが嬉しそうに迎えてくれます。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("プシーキーの迷宮が再設定されました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("プラバ防衛戦が始まりました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("[チーム経験値アップイベント] 始まりました！"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("い。"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("[チーム経験値アップイベント] 実施中です！"), String::from("#ff64ff"), String::from("[ 0時  0分 59秒]")),
-          ],
-          true,
-        ),
-        (vec![], false),
-        (vec![], false),
-        (vec![], false),
-        (vec![], false),
-        (
-          vec![
-            (String::from("◇本日の毎日課題：ステッドを退治"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：アビス深層96階以上クリア"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：ピリ辛ナテスコ煮"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("?フォレスト?が1分後に「ルーンの庭園」を訪問します。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("ルーンの庭園へ訪れると、妖精や精霊たちが嬉しそうに迎えてくれます。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("プシーキーの迷宮が再設定されました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("プラバ防衛戦が始まりました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("[チーム経験値アップイベント] 始まりました！"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("い。"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("[チーム経験値アップイベント] 実施中です！"), String::from("#ff64ff"), String::from("[ 0時  0分 59秒]")),
-          ],
-          true,
-        ),
-        (vec![], false),
-      ]
-    );
+    let (mut app, clock) = manual_app();
+    let path = &fixture("TWChatLog_2024_04_20.html");
+    read_past_file(&mut app, &clock, "TWChatLog_2024_04_19_large.html", "2024/04/19 00:00:00");
+    clock.set(utc("2024/04/20 00:00:00"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Rotated));
+    assert_eq!(app.get_messages(), channel_logs(system_messages_2024_04_20()));
   }
 
   #[test]
   fn read_file_smaller_past_file() {
-    let mut app = App::new();
-    let path ="test\\TWChatLog_2024_04_20.html";
-    let path = Path::new(path);
-    app.file = Some(File::open(path).unwrap());
-    app.file_size = std::fs::metadata("test\\TWChatLog_2024_04_19_small.html").unwrap().len();
-    app.date = NaiveDateTime::parse_from_str("2024/04/19 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    let date = NaiveDateTime::parse_from_str("2024/04/20 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    app.read_log(path, date).unwrap();
-    assert_eq!(app.messages,
-      [
-        (
-          vec![
-            (String::from("◇本日の毎日課題：ステッドを退治"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：アビス深層96階以上クリア"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：ピリ辛ナテスコ煮"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("?フォレスト?が1分後に「ルーンの庭園」を訪問します。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("ルーンの庭園へ訪れると、妖精や精霊たちが嬉しそうに迎えてくれます。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("プシーキーの迷宮が再設定されました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("プラバ防衛戦が始まりました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("[チーム経験値アップイベント] 始まりました！"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("い。"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("[チーム経験値アップイベント] 実施中です！"), String::from("#ff64ff"), String::from("[ 0時  0分 59秒]")),
-          ],
-          true,
-        ),
-        (vec![], false),
-        (vec![], false),
-        (vec![], false),
-        (vec![], false),
-        (
-          vec![
-            (String::from("◇本日の毎日課題：ステッドを退治"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：アビス深層96階以上クリア"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("◇本日の毎日課題：ピリ辛ナテスコ煮"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("?フォレスト?が1分後に「ルーンの庭園」を訪問します。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("ルーンの庭園へ訪れると、妖精や精霊たちが嬉しそうに迎えてくれます。"), String::from("#ff64ff"), String::from("[ 0時  0分  0秒]")),
-            (String::from("プシーキーの迷宮が再設定されました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("プラバ防衛戦が始まりました。"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("[チーム経験値アップイベント] 始まりました！"), String::from("#ff64ff"), String::from("[ 0時  0分  1秒]")),
-            (String::from("ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("い。"), String::from("#ff64ff"), String::from("[ 0時  0分  2秒]")),
-            (String::from("[チーム経験値アップイベント] 実施中です！"), String::from("#ff64ff"), String::from("[ 0時  0分 59秒]")),
-          ],
-          true,
-        ),
-        (vec![], false),
-      ]
-    );
+    let (mut app, clock) = manual_app();
+    let path = &fixture("TWChatLog_2024_04_20.html");
+    read_past_file(&mut app, &clock, "TWChatLog_2024_04_19_small.html", "2024/04/19 00:00:00");
+    clock.set(utc("2024/04/20 00:00:00"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Rotated));
+    assert_eq!(app.get_messages(), channel_logs(system_messages_2024_04_20()));
+  }
+
+  #[test]
+  fn read_file_header_date() {
+    let (mut app, clock) = manual_app();
+    let path = &fixture("TWChatLog_2024_04_20.html");
+    read_past_file(&mut app, &clock, "TWChatLog_2024_04_19_small.html", "2024/04/19 00:00:00");
+    clock.set(utc("2024/04/21 16:00:00"));
+    app.read_log(path).unwrap();
+    assert_eq!(app.tails[0].header, Some(LogHeader { date: log_date() }));
+    assert!(app.messages.iter(0).all(|message| message.time.date_naive() == log_date()));
+  }
+
+  #[test]
+  fn read_file_merge_wrapped() {
+    let (mut app, clock) = manual_app();
+    app.merge_wrapped = true;
+    let path = &fixture("TWChatLog_2024_04_20.html");
+    read_past_file(&mut app, &clock, "TWChatLog_2024_04_19_small.html", "2024/04/19 00:00:00");
+    clock.set(utc("2024/04/20 00:00:00"));
+    app.read_log(path).unwrap();
+    let mut expected = system_messages_2024_04_20();
+    expected[8].text = "ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してください。".to_string();
+    expected.remove(9);
+    let last = expected.pop().unwrap();
+    assert_eq!(view(&app, 5), expected);
+
+    clock.set(utc("2024/04/20 00:00:01"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(view(&app, 5).last(), Some(&ChatMessage { seq: 9, ..last }));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Unchanged));
   }
 
   #[test]
   fn read_file_no_data() {
+    let (mut app, clock) = manual_app();
+    let path = &fixture("TWChatLog_2024_04_20_no_data.html");
+    read_past_file(&mut app, &clock, "TWChatLog_2024_04_19_large.html", "2024/04/19 00:00:00");
+    clock.set(utc("2024/04/20 00:00:00"));
+    app.read_log(path).unwrap();
+    assert_eq!(app.get_messages(), channel_logs(vec![]));
+  }
+
+  fn view(app: &App, view: usize) -> Vec<ChatMessage> {
+    app.messages.iter(view).cloned().collect()
+  }
+
+  fn parse(app: &mut App, lines: Vec<String>) -> Result<()> {
+    app.parse_lines(0, lines.into_iter().enumerate().map(|(i, line)| (i as u64, line)).collect(), log_date())?;
+    app.commit()
+  }
+
+  fn texts(app: &App) -> Vec<&str> {
+    app.messages.iter(5).map(|message| message.text.as_str()).collect()
+  }
+
+  #[test]
+  fn read_memory_growth() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    clock.set(utc("2024/04/20 01:00:00"));
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[10時  0分  0秒]", "前"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Ok));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Unchanged));
+
+    source.append(path, &log_line("[10時  0分  1秒]", "一"));
+    let second = log_line("[10時  0分  2秒]", "二");
+    source.append(path, &second[..10]);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一"]);
+    source.append(path, &second[10..]);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一", "二"]);
+    assert_eq!(view(&app, 5)[1].time.to_rfc3339(), "2024-04-20T10:00:02+09:00");
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Unchanged));
+  }
+
+  #[test]
+  fn read_header_like_lines() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    app.backfill = Backfill::All;
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    clock.set(utc("2024/04/20 01:00:00"));
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[10時  0分  0秒]", "Talesweaver Chat Message Log"));
+    source.append(path, &log_line("[10時  0分  1秒]", "Date : 2024年 4月 20日"));
+    app.read_log(path).unwrap();
+    assert_eq!(texts(&app), ["Talesweaver Chat Message Log", "Date : 2024年 4月 20日"]);
+    assert_eq!(app.get_quarantine().count, 0);
+  }
+
+  #[test]
+  fn read_memory_rollover() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let past_path = Path::new("TWChatLog_2024_04_19.html");
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    source.create(past_path, &log_header(log_date().pred_opt().unwrap()));
+    clock.set(utc("2024/04/19 14:00:00"));
+    assert!(matches!(app.read_log(past_path).unwrap(), ReadStatus::Ok));
+    source.append(past_path, &log_line("[23時 59分 59秒]", "昨日"));
+    assert!(matches!(app.read_log(past_path).unwrap(), ReadStatus::Updated));
+
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[ 0時  0分  0秒]", "今日"));
+    clock.set(utc("2024/04/19 15:00:00"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Rotated));
+    assert_eq!(texts(&app), ["昨日", "今日"]);
+    let times: Vec<_> = app.messages.iter(5).map(|message| message.time.to_rfc3339()).collect();
+    assert_eq!(times, ["2024-04-19T23:59:59+09:00", "2024-04-20T00:00:00+09:00"]);
+  }
+
+  #[test]
+  fn read_memory_late_created() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    clock.set(utc("2024/04/20 01:00:00"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Ok));
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[10時  0分  0秒]", "一"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一"]);
+  }
+
+  #[test]
+  fn read_memory_truncated() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    clock.set(utc("2024/04/20 01:00:00"));
+    let header = log_header(log_date());
+    source.create(path, &header);
+    source.append(path, &log_line("[10時  0分  0秒]", "前"));
+    source.append(path, &log_line("[10時  0分  1秒]", "前"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Ok));
+    source.truncate(path, header.len());
+    source.append(path, &log_line("[10時  0分  2秒]", "一"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Truncated));
+    assert_eq!(texts(&app), ["一"]);
+    source.append(path, &log_line("[10時  0分  3秒]", "二"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一", "二"]);
+  }
+
+  #[test]
+  fn read_memory_replaced() {
+    let (mut app, clock) = manual_app();
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    clock.set(utc("2024/04/20 01:00:00"));
+    source.create(path, &log_header(log_date()));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Ok));
+    let mut content = log_header(log_date());
+    content.extend(log_line("[10時  0分  0秒]", "一"));
+    content.extend(log_line("[10時  0分  1秒]", "二"));
+    content.extend(log_line("[10時  0分  2秒]", "三"));
+    source.create(path, &content);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Rotated));
+    assert_eq!(texts(&app), ["一", "二", "三"]);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Unchanged));
+  }
+
+  fn backfill_source(app: &mut App, clock: &ManualClock) -> &'static Path {
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[ 9時  0分  0秒]", "一"));
+    source.append(path, &log_line("[ 9時 50分  0秒]", "二"));
+    source.append(path, &log_line("[10時  0分  0秒]", "三"));
+    clock.set(utc("2024/04/20 01:00:00"));
+    path
+  }
+
+  #[test]
+  fn read_memory_backfill() {
+    let (mut app, clock) = manual_app();
+    app.backfill = Backfill::All;
+    let path = backfill_source(&mut app, &clock);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一", "二", "三"]);
+    assert!(app.messages.iter(5).all(|message| message.backfilled));
+
+    let (mut app, clock) = manual_app();
+    app.backfill = Backfill::Lines(2);
+    let path = backfill_source(&mut app, &clock);
+    app.read_log(path).unwrap();
+    assert_eq!(texts(&app), ["二", "三"]);
+
+    let (mut app, clock) = manual_app();
+    app.backfill = Backfill::Minutes(30);
+    let path = backfill_source(&mut app, &clock);
+    app.read_log(path).unwrap();
+    assert_eq!(texts(&app), ["二", "三"]);
+    assert_eq!(view(&app, 5)[0].seq, 0);
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Unchanged));
+  }
+
+  #[test]
+  fn replay_log() {
+    let (mut app, clock) = manual_app();
+    let content = [log_header(log_date()), log_line("[10時  0分  0秒]", "一"), log_line("[10時  0分 10秒]", "二")].concat();
+    let source = ReplayLogSource::new(Path::new("TWChatLog_2024_04_20.html"), content, ReplaySpeed::Times(10), Box::new(clock.clone())).unwrap();
+    app.replay(source);
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一"]);
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Unchanged));
+    clock.advance(Duration::seconds(1));
+    app.read_logs().unwrap();
+    assert_eq!(texts(&app), ["一", "二"]);
+    assert!(app.messages.iter(5).all(|message| !message.backfilled));
+  }
+
+  #[test]
+  fn wake_schedule() {
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 14:00:00"));
+    assert_eq!(app.next_wake(), Some(std::time::Duration::from_secs(60 * 60)));
+    app.merge_wrapped = true;
+    app.parse_lines(0, vec![(0, line("[23時  0分  0秒]", "#ff64ff", &"あ".repeat(40)))], log_date()).unwrap();
+    assert_eq!(app.next_wake(), Some(WRAP_FLUSH_DELAY));
+
+    let (mut app, clock) = manual_app();
+    let content = [log_header(log_date()), log_line("[10時  0分  0秒]", "一"), log_line("[10時  0分 10秒]", "二")].concat();
+    let source = ReplayLogSource::new(Path::new("TWChatLog_2024_04_20.html"), content, ReplaySpeed::Times(1), Box::new(clock.clone())).unwrap();
+    app.replay(source);
+    assert_eq!(app.next_wake(), Some(REPLAY_TICK));
+    clock.advance(Duration::seconds(10));
+    assert_eq!(app.next_wake(), None);
+  }
+
+  #[test]
+  fn stop_merging_wrapped() {
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 01:00:00"));
+    let source = MemoryLogSource::new();
+    app.set_source(Box::new(source.clone()));
+    app.set_merge_wrapped(true).unwrap();
+    let path = &app.log_path();
+    source.create(path, &log_header(log_date()));
+    app.read_log(path).unwrap();
+    let wrapped = "あ".repeat(40);
+    source.append(path, &log_line("[10時  0分  0秒]", &wrapped));
+    app.read_log(path).unwrap();
+    assert!(texts(&app).is_empty());
+
+    app.set_merge_wrapped(false).unwrap();
+    assert_eq!(texts(&app), [wrapped.as_str()]);
+    source.append(path, &log_line("[10時  0分  1秒]", "二"));
+    app.read_log(path).unwrap();
+    assert_eq!(texts(&app), [wrapped.as_str(), "二"]);
+  }
+
+  #[test]
+  fn replay_keeps_sources() {
+    let (mut app, clock) = manual_app();
+    let content = [log_header(log_date()), log_line("[10時  0分  0秒]", "一")].concat();
+    let source = ReplayLogSource::new(Path::new("TWChatLog_2024_04_20.html"), content, ReplaySpeed::Instant, Box::new(clock)).unwrap();
+    app.replay(source);
+    let sources = app.sources();
+    assert!(app.add_source("sub", PathBuf::from("sub"), Box::new(MemoryLogSource::new())).is_err());
+    assert!(app.remove_source(PRIMARY_SOURCE).is_err());
+    assert!(app.set_log_dir(PathBuf::from("main")).is_err());
+    assert_eq!(app.sources(), sources);
+    assert!(!App::new().is_replay());
+  }
+
+  #[test]
+  fn read_multiple_sources() {
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 01:00:00"));
+    let main = MemoryLogSource::new();
+    let sub = MemoryLogSource::new();
+    app.set_source(Box::new(main.clone()));
+    app.set_log_dir(PathBuf::from("main")).unwrap();
+    app.add_source("sub", PathBuf::from("sub"), Box::new(sub.clone())).unwrap();
+    assert!(app.add_source("sub", PathBuf::from("sub2"), Box::new(MemoryLogSource::new())).is_err());
+    let main_path = log_file_path(Path::new("main"), log_date());
+    let sub_path = log_file_path(Path::new("sub"), log_date());
+    main.create(&main_path, &log_header(log_date()));
+    sub.create(&sub_path, &log_header(log_date()));
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Ok));
+
+    main.append(&main_path, &log_line("[10時  0分  0秒]", "一"));
+    main.append(&main_path, &log_line("[10時  0分  2秒]", "三"));
+    sub.append(&sub_path, &log_line("[10時  0分  1秒]", "二"));
+    sub.append(&sub_path, &log_line("[10時  0分  3秒]", "四"));
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Updated));
+    let sources: Vec<_> = app.messages.iter(5).map(|message| message.source.as_str()).collect();
+    assert_eq!(sources, ["main", "sub", "main", "sub"]);
+    assert_eq!(texts(&app), ["一", "二", "三", "四"]);
+    assert_eq!(app.messages.iter(5).map(|message| message.seq).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    app.source_filter = Some("sub".to_string());
+    assert_eq!(app.get_messages()[5].messages.iter().map(|message| message.text.as_str()).collect::<Vec<_>>(), ["二", "四"]);
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Unchanged));
+
+    assert!(app.remove_source(PRIMARY_SOURCE).is_err());
+    app.remove_source("sub").unwrap();
+    assert_eq!(app.sources(), [SourceConfig { name: "main".to_string(), log_dir: PathBuf::from("main") }]);
+  }
+
+  #[derive(Debug)]
+  struct BrokenSource;
+
+  impl LogSource for BrokenSource {
+    fn exists(&self, _: &Path) -> bool {
+      true
+    }
+
+    fn open(&mut self, _: &Path) -> Result<()> {
+      Ok(())
+    }
+
+    fn close(&mut self) {}
+
+    fn size(&mut self) -> Result<u64> {
+      bail!("broken")
+    }
+
+    fn read_from(&mut self, _: u64, _: u64) -> Result<Vec<u8>> {
+      bail!("broken")
+    }
+
+    fn identity(&self, _: &Path) -> Result<SourceIdentity> {
+      bail!("broken")
+    }
+  }
+
+  #[test]
+  fn read_past_failing_source() {
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 01:00:00"));
+    let main = MemoryLogSource::new();
+    app.set_source(Box::new(main.clone()));
+    app.set_log_dir(PathBuf::from("main")).unwrap();
+    app.add_source("broken", PathBuf::from("broken"), Box::new(BrokenSource)).unwrap();
+    app.add_source("sub", PathBuf::from("sub"), Box::new(main.clone())).unwrap();
+    let main_path = log_file_path(Path::new("main"), log_date());
+    let sub_path = log_file_path(Path::new("sub"), log_date());
+    main.create(&main_path, &log_header(log_date()));
+    main.create(&sub_path, &log_header(log_date()));
+    app.read_logs().unwrap();
+    assert_eq!(app.take_source_errors(), ["broken: broken"]);
+    assert!(app.is_failing());
+
+    main.append(&main_path, &log_line("[10時  0分  0秒]", "一"));
+    main.append(&sub_path, &log_line("[10時  0分  1秒]", "二"));
+    assert!(matches!(app.read_logs().unwrap(), ReadStatus::Updated));
+    assert_eq!(texts(&app), ["一", "二"]);
+    assert!(app.take_source_errors().is_empty());
+
+    app.remove_source("broken").unwrap();
+    app.read_logs().unwrap();
+    assert!(!app.is_failing());
+  }
+
+  #[test]
+  fn load_past_log() {
     let mut app = App::new();
-    let path ="test\\TWChatLog_2024_04_20_no_data.html";
-    let path = Path::new(path);
-    app.file = Some(File::open(path).unwrap());
-    app.file_size = std::fs::metadata("test\\TWChatLog_2024_04_19_large.html").unwrap().len();
-    app.date = NaiveDateTime::parse_from_str("2024/04/19 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    let date = NaiveDateTime::parse_from_str("2024/04/20 00:00:00", "%Y/%m/%d %H:%M:%S").unwrap();
-    app.read_log(path, date).unwrap();
-    assert_eq!(app.messages, [(vec![], false), (vec![], false), (vec![], false), (vec![], false), (vec![], false), (vec![], false), (vec![], false)]);
+    app.load_log(&fixture("TWChatLog_2024_04_20.html")).unwrap();
+    assert_eq!(app.get_messages(), channel_logs(system_messages_2024_04_20()));
+    let found = app.search("経験値アップ", &Channel::All);
+    assert_eq!(found.iter().map(|message| message.seq).collect::<Vec<_>>(), [7, 10]);
+    assert!(app.search("経験値アップ", &Channel::Public).is_empty());
+    assert_eq!(app.exp_summary().count, 0);
   }
 
   #[test]
   fn get_messages() {
     let mut app = App::new();
-    for i in 0..app.messages.len() {
-      app.messages[i].0.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-      app.messages[i].1 = true;
-    }
-    assert_eq!(app.get_messages(), [
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-    ]);
+    let channels = app.channels();
+    for (i, channel) in channels.iter().enumerate().skip(1) {
+      app.messages.push(message(i as u64, "test message", "#000000", channel.clone(), (0, 0, 0)), i);
+    }
+    let logs = app.get_messages();
+    assert_eq!(logs[0].messages.len(), channels.len() - 1);
+    for (i, log) in logs.iter().enumerate().skip(1) {
+      assert_eq!(log.messages, vec![message(i as u64, "test message", "#000000", log.channel.clone(), (0, 0, 0))]);
+    }
+    assert!(logs.iter().all(|log| log.updated));
 
-    for i in 0..app.messages.len() {
-      app.messages[i].0 = Vec::new();
-      for _ in 0..600 {
-        app.messages[i].0.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-        app.messages[i].1 = true;
-      }
+    let mut app = App::new();
+    app.messages.push(message(0, "test message", "#000000", Channel::System, (0, 0, 0)), 5);
+    for seq in 1..601 {
+      app.messages.push(message(seq, "test message", "#000000", Channel::Public, (0, 0, 0)), 1);
+    }
+    for log in app.get_messages() {
+      let expected: Vec<_> = match log.channel {
+        Channel::All | Channel::Public => (101..601).map(|seq| message(seq, "test message", "#000000", Channel::Public, (0, 0, 0))).collect(),
+        Channel::System => vec![message(0, "test message", "#000000", Channel::System, (0, 0, 0))],
+        _ => Vec::new(),
+      };
+      assert_eq!(log.updated, !expected.is_empty());
+      assert_eq!(log.messages, expected);
+    }
+
+    app.limit = false;
+    assert_eq!(app.get_messages()[0].messages.len(), 601);
+    assert_eq!(app.get_messages()[1].messages.len(), 600);
+  }
+
+  #[test]
+  fn get_messages_range() {
+    let mut app = App::new();
+    for seq in 0..1000 {
+      app.messages.push(message(seq, "test message", "#000000", Channel::Public, (0, 0, 0)), 1);
+    }
+    let seqs = |messages: Vec<ChatMessage>| messages.iter().map(|message| message.seq).collect::<Vec<_>>();
+    assert_eq!(seqs(app.get_messages_range(&Channel::Public, None, 3)), [997, 998, 999]);
+    assert_eq!(seqs(app.get_messages_range(&Channel::All, Some(997), 2)), [995, 996]);
+    assert_eq!(seqs(app.get_messages_range(&Channel::Public, Some(2), 100)), [0, 1]);
+    assert!(app.get_messages_range(&Channel::System, None, 100).is_empty());
+    assert!(app.get_messages_range(&Channel::Custom("取引".to_string()), None, 100).is_empty());
+  }
+
+  #[test]
+  fn messages_after() {
+    let mut app = App::new();
+    assert_eq!(app.last_seq(), None);
+    let lines = ["#ff64ff", "#ffffff", "#ff64ff"].iter().enumerate().map(|(i, color)| line(&format!("[10時  0分  {}秒]", i), color, &i.to_string())).collect();
+    parse(&mut app, lines).unwrap();
+    assert_eq!(app.last_seq(), Some(2));
+    let seqs = |messages: Vec<ChatMessage>| messages.iter().map(|message| message.seq).collect::<Vec<_>>();
+    assert_eq!(seqs(app.messages_after(None)), [0, 1, 2]);
+    assert_eq!(seqs(app.messages_after(Some(0))), [1, 2]);
+    assert!(app.messages_after(app.last_seq()).is_empty());
+    app.source_filter = Some("sub".to_string());
+    assert!(app.messages_after(None).is_empty());
+  }
+
+  #[test]
+  fn messages_after_limit() {
+    let mut app = App::new();
+    app.messages.push(message(0, "system", "#ff64ff", Channel::System, (0, 0, 0)), 5);
+    for seq in 1..=DISPLAY_LIMIT as u64 + 1 {
+      app.messages.push(message(seq, "test message", "#000000", Channel::Public, (0, 0, 0)), 1);
+    }
+    let seqs = |messages: Vec<ChatMessage>| messages.iter().map(|message| message.seq).collect::<Vec<_>>();
+    // The system message is still shown in its own view.
+    let expected: Vec<u64> = std::iter::once(0).chain(2..=DISPLAY_LIMIT as u64 + 1).collect();
+    assert_eq!(seqs(app.messages_after(None)), expected);
+    assert_eq!(seqs(app.messages_after(Some(0))), expected[1..]);
+    app.limit = false;
+    assert_eq!(app.messages_after(None).len(), DISPLAY_LIMIT + 2);
+  }
+
+  #[test]
+  fn channel_capacity() {
+    let config = ChannelConfig::parse(r##"{ "colors": { "#ff64ff": "system" }, "capacity": { "all": 3, "system": 2 } }"##).unwrap();
+    let mut app = App::new();
+    app.set_color_map(config.color_map().unwrap());
+    let lines = (0..4).map(|i| line(&format!("[10時  0分  {}秒]", i), "#ff64ff", &i.to_string())).collect();
+    parse(&mut app, lines).unwrap();
+    assert_eq!(texts(&app), ["2", "3"]);
+    assert_eq!(app.messages.len(0), 3);
+  }
+
+  #[test]
+  fn unbounded_history() {
+    let config = ChannelConfig::parse(r##"{ "colors": { "#ff64ff": "system" }, "capacity": { "all": 3, "system": 2 } }"##).unwrap();
+    let mut app = App::new();
+    app.set_color_map(config.color_map().unwrap());
+    app.set_unbounded();
+    let lines = (0..4).map(|i| line(&format!("[10時  0分  {}秒]", i), "#ff64ff", &i.to_string())).collect();
+    parse(&mut app, lines).unwrap();
+    assert_eq!(texts(&app), ["0", "1", "2", "3"]);
+    assert_eq!(app.messages.len(0), 4);
+  }
+
+  #[test]
+  fn archive_read_lines() {
+    let source = MemoryLogSource::new();
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    source.create(path, &log_header(log_date()));
+    source.append(path, &log_line("[10時  0分  0秒]", "一"));
+    source.append(path, &log_line("[10時  0分  1秒]", "二"));
+    let archived = |app: &App| -> Vec<(u64, String)> {
+      app.archive.as_ref().unwrap().messages(path).unwrap().into_iter().map(|message| (message.seq, message.text)).collect()
+    };
+
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 01:00:00"));
+    app.set_source(Box::new(source.clone()));
+    app.set_archive(Some(Archive::open_in_memory().unwrap()));
+    app.backfill = Backfill::All;
+    app.read_log(path).unwrap();
+    assert_eq!(archived(&app), [(1, "一".to_string()), (2, "二".to_string())]);
+
+    // Read again from the start, and then from the end of the file.
+    for backfill in [Backfill::All, Backfill::Off] {
+      let archive = app.archive.take();
+      let (mut next, clock) = manual_app();
+      clock.set(utc("2024/04/20 01:00:00"));
+      next.set_source(Box::new(source.clone()));
+      next.set_archive(archive);
+      next.backfill = backfill;
+      next.read_log(path).unwrap();
+      app = next;
     }
-    let mut expected = Vec::new();
-    for _ in 0..500 {
-      expected.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    }
-    assert_eq!(app.get_messages(), [
-      (expected.clone(), true),
-      (expected.clone(), true),
-      (expected.clone(), true),
-      (expected.clone(), true),
-      (expected.clone(), true),
-      (expected.clone(), true),
-      (expected.clone(), true),
+    source.append(path, &log_line("[10時  0分  2秒]", "三"));
+    // Kept even though it has no channel yet.
+    let unknown = line("[10時  0分  3秒]", "#123456", "新しい色") + "\r\n";
+    source.append(path, &encoding_rs::SHIFT_JIS.encode(&unknown).0);
+    app.read_log(path).unwrap();
+    assert_eq!(archived(&app), [(1, "一".to_string()), (2, "二".to_string()), (3, "三".to_string()), (4, "新しい色".to_string())]);
+    assert!(app.archived.is_empty());
+
+    // New lines over old ones once the file starts over.
+    source.truncate(path, log_header(log_date()).len());
+    source.append(path, &log_line("[11時  0分  0秒]", "四"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Truncated));
+    assert_eq!(archived(&app), [(1, "一".to_string()), (5, "四".to_string()), (2, "二".to_string()), (3, "三".to_string()), (4, "新しい色".to_string())]);
+  }
+
+  #[test]
+  fn archive_write_error() {
+    let dir = TempDir::new("archive-error");
+    let database = dir.path().join("archive.sqlite3");
+    let source = MemoryLogSource::new();
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    source.create(path, &log_header(log_date()));
+    let (mut app, clock) = manual_app();
+    clock.set(utc("2024/04/20 01:00:00"));
+    app.set_source(Box::new(source.clone()));
+    app.set_archive(Some(Archive::open(&database).unwrap()));
+    app.read_log(path).unwrap();
+
+    let other = rusqlite::Connection::open(&database).unwrap();
+    other.execute_batch("ALTER TABLE messages RENAME TO moved").unwrap();
+    source.append(path, &log_line("[10時  0分  0秒]", "一"));
+    assert!(matches!(app.read_log(path).unwrap(), ReadStatus::Updated));
+    assert_eq!(app.take_archive_errors().len(), 1);
+    source.append(path, &log_line("[10時  0分  1秒]", "二"));
+    app.read_log(path).unwrap();
+    assert!(app.take_archive_errors().is_empty());
+    assert_eq!(app.archived.len(), 2);
+    // Only the latest ones wait once too many have piled up.
+    let waiting = app.archived.clone();
+    app.archived = vec![waiting[0].clone(); ARCHIVE_BACKLOG];
+    app.archived.extend(waiting);
+    app.read_log(path).unwrap();
+    assert_eq!(app.archived.len(), ARCHIVE_BACKLOG);
+
+    other.execute_batch("ALTER TABLE moved RENAME TO messages").unwrap();
+    app.read_log(path).unwrap();
+    assert_eq!(app.take_archive_errors(), ["書き込めなかった 2 件のメッセージはアーカイブされていません。"]);
+    let archived: Vec<_> = app.archive.as_ref().unwrap().messages(path).unwrap().into_iter().map(|message| message.text).collect();
+    assert_eq!(archived, ["一", "二"]);
+  }
+
+  #[test]
+  fn quarantine_unknown_lines() {
+    let lines = vec![
+      line("[ 0時  5分 44秒]", "#c8ffc8", "JADEN : てｓｔ"),
+      line("[ 0時  5分 45秒]", "#123456", "新しい色"),
+      r##"<p>broken</p>"##.to_string(),
+      line("[ 0時  5分 46秒]", "#c8ffc8", "JADEN : a"),
+    ];
+    let mut app = App::new();
+    parse(&mut app, lines.clone()).unwrap();
+    assert_eq!(view(&app, 1), vec![
+      ChatMessage { speaker: Some("JADEN".to_string()), ..message(0, "てｓｔ", "#c8ffc8", Channel::Public, (0, 5, 44)) },
+      ChatMessage { speaker: Some("JADEN".to_string()), ..message(2, "a", "#c8ffc8", Channel::Public, (0, 5, 46)) },
     ]);
+    let report = app.get_quarantine();
+    assert_eq!(report.count, 2);
+    // A line that does not parse is set aside at once, a message of an
+    // unknown color once it is numbered.
+    assert_eq!(report.lines[0].raw, lines[2]);
+    assert_eq!(report.lines[0].message, None);
+    assert_eq!(report.lines[1].raw, lines[1]);
+    assert_eq!(report.lines[1].message, Some(message(1, "新しい色", "#123456", Channel::Unknown, (0, 5, 45))));
+
+    let mut app = App::new();
+    app.parse_mode = ParseMode::Strict;
+    assert!(parse(&mut app, lines).is_err());
+    assert_eq!(app.get_quarantine().count, 0);
+  }
+
+  #[test]
+  fn custom_channel() {
+    let config = ChannelConfig::parse(r##"{ "channels": ["取引"], "colors": { "#123456": "取引", "#c8ffc8": "public" } }"##).unwrap();
+    let mut app = App::new();
+    app.set_color_map(config.color_map().unwrap());
+    assert_eq!(app.views.len(), 8);
+    assert_eq!(app.channels()[7], Channel::Custom("取引".to_string()));
+    parse(&mut app, vec![
+      line("[ 0時  5分 45秒]", "#123456", "売ります"),
+    ]).unwrap();
+    let expected = vec![message(0, "売ります", "#123456", Channel::Custom("取引".to_string()), (0, 5, 45))];
+    assert_eq!(view(&app, 0), expected);
+    assert_eq!(view(&app, 7), expected);
+  }
 
-    for i in 0..app.messages.len() {
-      app.messages[i].0 = Vec::new();
-      app.messages[i].0.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-      app.messages[i].1 = true;
-    }
-    for _ in 0..600 {
-      app.messages[1].0.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-      app.messages[1].1 = true;
-    }
-    let mut expected = Vec::new();
-    for _ in 0..500 {
-      expected.push(("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    }
-    assert_eq!(app.get_messages(), [
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (expected, true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
-      (vec![("test message".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string())], true),
+  #[test]
+  fn whisper_partners() {
+    let lines = vec![
+      line("[ 1時  0分  0秒]", "#64ff64", "Alice : こんにちは"),
+      line("[ 1時  0分  5秒]", "#64ff64", "Aliceへ : どうも"),
+      line("[ 1時  1分  0秒]", "#64ff64", "Bob : 元気？"),
+    ];
+    let mut app = App::new();
+    parse(&mut app, lines).unwrap();
+    let alice = Whisper { direction: WhisperDirection::Incoming, partner: "Alice".to_string() };
+    // Either sent to Alice or from someone named "Aliceへ".
+    let to_alice = Whisper { direction: WhisperDirection::Unknown, partner: "Aliceへ".to_string() };
+    assert_eq!(view(&app, 2)[0], ChatMessage { speaker: Some("Alice".to_string()), whisper: Some(alice), ..message(0, "こんにちは", "#64ff64", Channel::Whisper, (1, 0, 0)) });
+    assert_eq!(view(&app, 2)[1], ChatMessage { speaker: Some("Aliceへ".to_string()), whisper: Some(to_alice), ..message(1, "どうも", "#64ff64", Channel::Whisper, (1, 0, 5)) });
+    assert_eq!(app.whisper_partners(), vec![
+      WhisperPartner { name: "Bob".to_string(), last_time: jst(log_date(), NaiveTime::from_hms_opt(1, 1, 0).unwrap()), last_direction: WhisperDirection::Incoming },
+      WhisperPartner { name: "Aliceへ".to_string(), last_time: jst(log_date(), NaiveTime::from_hms_opt(1, 0, 5).unwrap()), last_direction: WhisperDirection::Unknown },
+      WhisperPartner { name: "Alice".to_string(), last_time: jst(log_date(), NaiveTime::from_hms_opt(1, 0, 0).unwrap()), last_direction: WhisperDirection::Incoming },
     ]);
   }
 
+  fn push_exp(app: &mut App, times: &[(u32, u32, u32, u32)]) {
+    for time in times {
+      let seq = app.messages.len(5) as u64;
+      let date = NaiveDate::from_ymd_opt(2000, 1, time.0).unwrap();
+      let time = jst(date, NaiveTime::from_hms_opt(time.1, time.2, time.3).unwrap());
+      app.messages.push(ChatMessage { time, ..message(seq, "経験値が 30000 上がりました。", "#000000", Channel::System, (0, 0, 0)) }, 5);
+    }
+  }
+
   #[test]
-  fn calc_exp() {
+  fn exp_summary() {
     let mut app = App::new();
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  1秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  2秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  3秒]".to_string()));
-    app.messages[5].1 = true;
-    let now = NaiveDateTime::parse_from_str("2000/01/01 15:00:3", "%Y/%m/%d %H:%M:%S").unwrap();
-    let exp = app.calc_exp(now);
+    push_exp(&mut app, &[(1, 23, 0, 0), (1, 23, 30, 0), (2, 0, 0, 0)]);
+    let summary = app.exp_summary();
+    assert_eq!((summary.total, summary.count, summary.per_hour), (90000, 3, 90000));
+    assert_eq!(summary.first_time.unwrap().to_rfc3339(), "2000-01-01T23:00:00+09:00");
+    assert_eq!(summary.last_time.unwrap().to_rfc3339(), "2000-01-02T00:00:00+09:00");
+  }
+
+  #[test]
+  fn calc_exp() {
+    let (mut app, clock) = manual_app();
+    push_exp(&mut app, &[(2, 0, 0, 0), (2, 0, 0, 1), (2, 0, 0, 2), (2, 0, 0, 3)]);
+    clock.set(utc("2000/01/01 15:00:3"));
+    let exp = app.calc_exp();
     assert_eq!(exp, (40000, 40000 * 60, 40000 * 60 * 60));
 
-    let mut app = App::new();
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  1秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  1秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  2秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  2秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  3秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  3秒]".to_string()));
-    app.messages[5].1 = true;
-    let now = NaiveDateTime::parse_from_str("2000/01/01 15:00:3", "%Y/%m/%d %H:%M:%S").unwrap();
-    let exp = app.calc_exp(now);
+    let (mut app, clock) = manual_app();
+    push_exp(&mut app, &[(2, 0, 0, 0), (2, 0, 0, 0), (2, 0, 0, 1), (2, 0, 0, 1), (2, 0, 0, 2), (2, 0, 0, 2), (2, 0, 0, 3), (2, 0, 0, 3)]);
+    clock.set(utc("2000/01/01 15:00:3"));
+    let exp = app.calc_exp();
     assert_eq!(exp, (240000 / 3, 240000 / 3 * 60, 240000 / 3 * 60 * 60));
 
-    let mut app = App::new();
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  3秒]".to_string()));
-    app.messages[5].1 = true;
-    let now = NaiveDateTime::parse_from_str("2000/01/01 15:00:3", "%Y/%m/%d %H:%M:%S").unwrap();
-    let exp = app.calc_exp(now);
+    let (mut app, clock) = manual_app();
+    push_exp(&mut app, &[(2, 0, 0, 0), (2, 0, 0, 3)]);
+    clock.set(utc("2000/01/01 15:00:3"));
+    let exp = app.calc_exp();
     assert_eq!(exp, (20000, 20000 * 60, 20000 * 60 * 60));
 
-    app.messages[5].0.clear();
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  1秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  2秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  3秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  4秒]".to_string()));
-    app.messages[5].1 = true;
-    let now = NaiveDateTime::parse_from_str("2000/01/01 15:00:4", "%Y/%m/%d %H:%M:%S").unwrap();
-    let exp = app.calc_exp(now);
+    app.messages.clear();
+    push_exp(&mut app, &[(2, 0, 0, 0), (2, 0, 0, 1), (2, 0, 0, 2), (2, 0, 0, 3), (2, 0, 0, 4)]);
+    clock.set(utc("2000/01/01 15:00:4"));
+    let exp = app.calc_exp();
     assert_eq!(exp, (40000, 40000 * 60, 40000 * 60 * 60));
 
-    app.messages[5].0.clear();
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[23時 59分 57秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[23時 59分 58秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[23時 59分 59秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  0秒]".to_string()));
-    app.messages[5].0.push(("経験値が 30000 上がりました。".to_string(), "#000000".to_string(), "[ 0時  0分  1秒]".to_string()));
-    app.messages[5].1 = true;
-    let now = NaiveDateTime::parse_from_str("2000/01/01 15:00:1", "%Y/%m/%d %H:%M:%S").unwrap();
-    let exp = app.calc_exp(now);
+    app.messages.clear();
+    push_exp(&mut app, &[(1, 23, 59, 57), (1, 23, 59, 58), (1, 23, 59, 59), (2, 0, 0, 0), (2, 0, 0, 1)]);
+    clock.set(utc("2000/01/01 15:00:1"));
+    let exp = app.calc_exp();
     assert_eq!(exp, (40000, 40000 * 60, 40000 * 60 * 60));
   }
+
+  #[test]
+  fn midnight_crossing() {
+    let lines = vec![
+      line("[23時 59分 59秒]", "#ff64ff", "a"),
+      line("[ 0時  0分  0秒]", "#ff64ff", "b"),
+      line("[ 0時  0分  1秒]", "#ff64ff", "c"),
+    ];
+    let mut app = App::new();
+    parse(&mut app, lines).unwrap();
+    let times: Vec<_> = app.messages.iter(5).map(|message| message.time.to_rfc3339()).collect();
+    assert_eq!(times, ["2024-04-20T23:59:59+09:00", "2024-04-21T00:00:00+09:00", "2024-04-21T00:00:01+09:00"]);
+  }
+
+  #[test]
+  fn serialize_message() {
+    let message = ChatMessage { speaker: Some("JADEN".to_string()), ..message(3, "a", "#c8ffc8", Channel::Public, (23, 16, 12)) };
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json, serde_json::json!({
+      "seq": 3,
+      "source": "main",
+      "speaker": "JADEN",
+      "whisper": null,
+      "text": "a",
+      "color": "#c8ffc8",
+      "channel": "public",
+      "time": "2024-04-20T23:16:12+09:00",
+      "backfilled": false,
+    }));
+  }
 }
@@ -0,0 +1,128 @@
+use chrono::NaiveTime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+pub static LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r##"^<font.+> (.+) </font> <font.+color="(.+)">(.+)</font></br>$"##).unwrap());
+static TIME: Lazy<Regex> = Lazy::new(|| Regex::new(r##"^\[\s?(\d+)時\s+(\d+)分\s+(\d+)秒\]$"##).unwrap());
+
+// The client wraps a message body at 80-81 Shift_JIS bytes and writes the rest
+// as new lines with the same time and color.
+pub const WRAP_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawLine {
+  pub raw: String,
+  pub time: String,
+  pub color: String,
+  pub body: String,
+  // Where the line starts in its file. A merged line starts with its first.
+  pub offset: u64,
+}
+
+impl RawLine {
+  pub fn parse(line: &str) -> Option<RawLine> {
+    let captures = LINE.captures(line)?;
+    Some(RawLine {
+      raw: line.to_string(),
+      time: captures[1].to_string(),
+      color: captures[2].to_string(),
+      body: captures[3].to_string(),
+      offset: 0,
+    })
+  }
+
+  pub fn time_of_day(&self) -> Option<NaiveTime> {
+    let captures = TIME.captures(&self.time)?;
+    NaiveTime::from_hms_opt(captures[1].parse().ok()?, captures[2].parse().ok()?, captures[3].parse().ok()?)
+  }
+
+  fn width(&self) -> usize {
+    let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(&self.body);
+    bytes.len()
+  }
+
+  fn continues(&self, line: &RawLine) -> bool {
+    self.time == line.time && self.color == line.color
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct Reassembler {
+  held: Option<(RawLine, usize)>,
+}
+
+impl Reassembler {
+  pub fn new() -> Self {
+    Reassembler { held: None }
+  }
+
+  pub fn push(&mut self, line: RawLine) -> Option<RawLine> {
+    let width = line.width();
+    match self.held.take() {
+      Some((mut held, held_width)) if WRAP_WIDTH <= held_width && held.continues(&line) => {
+        held.raw.push_str("\r\n");
+        held.raw.push_str(&line.raw);
+        held.body.push_str(&line.body);
+        self.held = Some((held, width));
+        None
+      }
+      held => {
+        self.held = Some((line, width));
+        held.map(|(held, _)| held)
+      }
+    }
+  }
+
+  pub fn flush(&mut self) -> Option<RawLine> {
+    self.held.take().map(|(held, _)| held)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.held.is_none()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing;
+
+  fn line(time: &str, color: &str, body: &str) -> RawLine {
+    RawLine::parse(&testing::line(time, color, body)).unwrap()
+  }
+
+  #[test]
+  fn reassemble_wrapped_lines() {
+    let mut reassembler = Reassembler::new();
+    let first = line("[ 0時  0分  2秒]", "#ff64ff", "ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してくださ");
+    let second = line("[ 0時  0分  2秒]", "#ff64ff", "い。");
+    let third = line("[ 0時  0分 59秒]", "#ff64ff", "[チーム経験値アップイベント] 実施中です！");
+    assert_eq!(reassembler.push(first.clone()), None);
+    assert_eq!(reassembler.push(second.clone()), None);
+    let merged = reassembler.push(third.clone()).unwrap();
+    assert_eq!(merged.body, "ランダムレイドバトルに参加できます。[ クラド ]でポータルを利用して入場してください。");
+    assert_eq!(merged.raw, format!("{}\r\n{}", first.raw, second.raw));
+    assert_eq!(reassembler.flush(), Some(third));
+    assert!(reassembler.is_empty());
+  }
+
+  #[test]
+  fn keep_short_lines() {
+    let mut reassembler = Reassembler::new();
+    let first = line("[ 0時  0分  1秒]", "#ff64ff", "プラバ防衛戦が始まりました。");
+    let second = line("[ 0時  0分  1秒]", "#ff64ff", "[チーム経験値アップイベント] 始まりました！");
+    assert_eq!(reassembler.push(first.clone()), None);
+    assert_eq!(reassembler.push(second.clone()), Some(first));
+    assert_eq!(reassembler.flush(), Some(second));
+  }
+
+  #[test]
+  fn keep_other_color_lines() {
+    let mut reassembler = Reassembler::new();
+    let first = line("[23時 37分 27秒]", "#c896c8", "叫ぶ : 誰か [20thメモリアルボックス] でアイテムを獲得しました。 : ペットSスキルス");
+    let second = line("[23時 37分 27秒]", "#ff64ff", "クロールボックス(真・Lv2)");
+    assert_eq!(reassembler.push(first.clone()), None);
+    assert_eq!(reassembler.push(second.clone()), Some(first));
+    assert_eq!(reassembler.flush(), Some(second));
+  }
+}
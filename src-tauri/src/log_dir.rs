@@ -0,0 +1,119 @@
+use std::{fs, path::{Path, PathBuf}};
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+
+pub const DEFAULT_LOG_DIR: &str = "C:\\Nexon\\TalesWeaver\\ChatLog";
+
+const CHAT_LOG_DIR: &str = "ChatLog";
+
+// Install folders relative to a drive root or a Wine prefix's drive_c.
+const INSTALL_DIRS: [&[&str]; 4] = [
+  &["Nexon", "TalesWeaver"],
+  &["Program Files (x86)", "Steam", "steamapps", "common", "TalesWeaver"],
+  &["Program Files", "Steam", "steamapps", "common", "TalesWeaver"],
+  &["SteamLibrary", "steamapps", "common", "TalesWeaver"],
+];
+
+pub fn log_file_name(date: NaiveDate) -> String {
+  date.format("TWChatLog_%Y_%m_%d.html").to_string()
+}
+
+pub fn log_file_path(dir: &Path, date: NaiveDate) -> PathBuf {
+  dir.join(log_file_name(date))
+}
+
+pub fn log_file_date(name: &str) -> Option<NaiveDate> {
+  let date = NaiveDate::parse_from_str(name, "TWChatLog_%Y_%m_%d.html").ok()?;
+  if log_file_name(date) == name {
+    Some(date)
+  } else {
+    None
+  }
+}
+
+pub fn validate_log_dir(dir: &Path) -> Result<()> {
+  if !dir.is_dir() {
+    bail!("{} はフォルダではありません。", dir.display());
+  }
+  let entries = fs::read_dir(dir).with_context(|| format!("{} を読み込めません。", dir.display()))?;
+  for entry in entries.flatten() {
+    if entry.file_name().to_str().and_then(log_file_date).is_some() {
+      return Ok(());
+    }
+  }
+  bail!("{} にチャットログ (TWChatLog_YYYY_MM_DD.html) がありません。", dir.display());
+}
+
+pub fn candidates() -> Vec<PathBuf> {
+  let mut dirs = Vec::new();
+  for root in roots() {
+    for install in INSTALL_DIRS.iter() {
+      let mut dir = root.clone();
+      dir.extend(install.iter());
+      dirs.push(dir.join(CHAT_LOG_DIR));
+    }
+  }
+  for steam in steam_roots() {
+    dirs.push(steam.join("steamapps").join("common").join("TalesWeaver").join(CHAT_LOG_DIR));
+  }
+  dirs
+}
+
+pub fn discover_log_dirs() -> Vec<PathBuf> {
+  candidates().into_iter().filter(|dir| validate_log_dir(dir).is_ok()).collect()
+}
+
+#[cfg(windows)]
+fn roots() -> Vec<PathBuf> {
+  ('C'..='Z').map(|drive| PathBuf::from(format!("{}:\\", drive))).collect()
+}
+
+#[cfg(not(windows))]
+fn roots() -> Vec<PathBuf> {
+  let mut prefixes = Vec::new();
+  if let Some(prefix) = std::env::var_os("WINEPREFIX") {
+    prefixes.push(PathBuf::from(prefix));
+  }
+  if let Some(home) = std::env::var_os("HOME") {
+    prefixes.push(Path::new(&home).join(".wine"));
+  }
+  prefixes.into_iter().map(|prefix| prefix.join("drive_c")).collect()
+}
+
+#[cfg(windows)]
+fn steam_roots() -> Vec<PathBuf> {
+  Vec::new()
+}
+
+#[cfg(not(windows))]
+fn steam_roots() -> Vec<PathBuf> {
+  match std::env::var_os("HOME") {
+    Some(home) => {
+      let home = Path::new(&home);
+      vec![home.join(".steam").join("steam"), home.join(".local").join("share").join("Steam")]
+    }
+    _ => Vec::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn log_file_names() {
+    let date = NaiveDate::from_ymd_opt(2024, 4, 20).unwrap();
+    assert_eq!(log_file_path(Path::new("ChatLog"), date), Path::new("ChatLog").join("TWChatLog_2024_04_20.html"));
+    assert_eq!(log_file_date("TWChatLog_2024_04_20.html"), Some(date));
+    assert_eq!(log_file_date("TWChatLog_2024_4_20.html"), None);
+    assert_eq!(log_file_date("TWChatLog_2024_04_20_no_data.html"), None);
+  }
+
+  #[test]
+  fn validate_log_dirs() {
+    assert!(validate_log_dir(&crate::testing::fixture_dir()).is_ok());
+    assert!(validate_log_dir(&crate::testing::fixture_dir().with_file_name("src")).is_err());
+    assert!(validate_log_dir(&crate::testing::fixture("TWChatLog_2024_04_20.html")).is_err());
+    assert!(candidates().iter().all(|dir| dir.ends_with(CHAT_LOG_DIR)));
+  }
+}
@@ -1,21 +1,70 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use app::{App, ReadStatus};
+use app::{discover_log_dirs, list_logs, log_file_date, validate_log_dir, App, Archive, Backfill, Channel, ChannelConfig, ChannelLog, ChatMessage, ColorMap, ExpSummary, FsLogSource, LogFileInfo, LogWatcher, QuarantineReport, ReplayLogSource, ReplaySpeed, SourceConfig, SystemClock, WhisperPartner};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tauri::{Builder, CustomMenuItem, Manager, Menu, MenuItem, Submenu};
-use tauri_plugin_store::StoreBuilder;
-use std::{sync::Mutex, thread, time::Duration};
-use chrono_tz::Asia::Tokyo;
-use chrono::{Datelike, TimeZone, Utc};
-use std::path::Path;
+use tauri::{api::dialog::FileDialogBuilder, AppHandle, Builder, CustomMenuItem, Manager, Menu, MenuHandle, MenuItem, Submenu};
+use tauri_plugin_store::{Store, StoreBuilder};
+use std::{sync::{mpsc, Mutex}, thread, time::Duration};
+use std::path::{Path, PathBuf};
 
 const STORE_NAME: &str = "store.dat";
+const CHANNEL_CONFIG_NAME: &str = "channels.json";
+const DATABASE_NAME: &str = "archive.sqlite3";
+// How often a log folder that is not there yet is looked for.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+// How much of today's log the last lines and last minutes choices read, unless
+// "backfill_lines" and "backfill_minutes" are set in the store.
+const DEFAULT_BACKFILL_LINES: usize = 100;
+const DEFAULT_BACKFILL_MINUTES: i64 = 30;
+const REPLAY_SPEEDS: [(&str, &str, ReplaySpeed); 3] = [
+  ("replay_1", "等速", ReplaySpeed::Times(1)),
+  ("replay_10", "10倍速", ReplaySpeed::Times(10)),
+  ("replay_instant", "一瞬で", ReplaySpeed::Instant),
+];
 
 fn main() {
+  let context = tauri::generate_context!();
+  let mut state = App::new();
+  let config_error = match tauri::api::path::app_config_dir(context.config()) {
+    Some(dir) => match ChannelConfig::load(&dir.join(CHANNEL_CONFIG_NAME)).and_then(|config| config.color_map()) {
+      Ok(color_map) => {
+        state.set_color_map(color_map);
+        None
+      }
+      Err(e) => {
+        state.set_color_map(ColorMap::default());
+        Some(format!("{:#}", e))
+      }
+    },
+    _ => None,
+  };
+  let channels = state.channels();
+  let log_dir = CustomMenuItem::new("log_dir".to_string(), "ログフォルダを選択...");
   let exit = CustomMenuItem::new("exit".to_string(), "終了");
-  let file = Submenu::new("ファイル", Menu::new().add_item(exit));
+  let backfill_off = CustomMenuItem::new("backfill_off".to_string(), "読み込まない");
+  let backfill_all = CustomMenuItem::new("backfill_all".to_string(), "すべて");
+  let backfill_lines = CustomMenuItem::new("backfill_lines".to_string(), format!("最後の{}行", DEFAULT_BACKFILL_LINES));
+  let backfill_minutes = CustomMenuItem::new("backfill_minutes".to_string(), format!("最後の{}分", DEFAULT_BACKFILL_MINUTES));
+  let backfill = Submenu::new("起動時に今日のログを読み込む", Menu::new()
+    .add_item(backfill_off)
+    .add_item(backfill_all)
+    .add_item(backfill_lines)
+    .add_item(backfill_minutes));
+  let mut replay = Menu::new();
+  for (id, name, _) in REPLAY_SPEEDS.iter() {
+    replay = replay.add_item(CustomMenuItem::new(id.to_string(), format!("{}...", name)));
+  }
+  let replay = Submenu::new("ログを再生", replay
+    .add_native_item(MenuItem::Separator)
+    .add_item(CustomMenuItem::new("replay_stop".to_string(), "再生を停止")));
+  let file = Submenu::new("ファイル", Menu::new()
+    .add_item(log_dir)
+    .add_submenu(backfill)
+    .add_submenu(replay)
+    .add_native_item(MenuItem::Separator)
+    .add_item(exit));
   let all = CustomMenuItem::new("view0".to_string(), "全体").accelerator("1");
   let public = CustomMenuItem::new("view1".to_string(), "一般").accelerator("2");
   let private = CustomMenuItem::new("view2".to_string(), "耳打ち").accelerator("3");
@@ -31,37 +80,50 @@ fn main() {
   let club_auto_scroll = CustomMenuItem::new("auto_scroll4".to_string(), "クラブ").accelerator("Ctrl+5");
   let system_auto_scroll = CustomMenuItem::new("auto_scroll5".to_string(), "システム").accelerator("Ctrl+6");
   let server_auto_scroll = CustomMenuItem::new("auto_scroll6".to_string(), "叫び").accelerator("Ctrl+7");
-  let auto_scroll = Submenu::new("オートスクロール", Menu::new()
+  let mut auto_scroll = Menu::new()
     .add_item(all_auto_scroll)
     .add_item(public_auto_scroll)
     .add_item(private_auto_scroll)
     .add_item(team_auto_scroll)
     .add_item(club_auto_scroll)
     .add_item(system_auto_scroll)
-    .add_item(server_auto_scroll));
+    .add_item(server_auto_scroll);
+  for (i, channel) in channels.iter().enumerate().skip(Channel::VIEWS.len()) {
+    auto_scroll = auto_scroll.add_item(CustomMenuItem::new(format!("auto_scroll{}", i), channel.name()));
+  }
+  let auto_scroll = Submenu::new("オートスクロール", auto_scroll);
   let verbose = CustomMenuItem::new("verbose".to_string(), "時間表示").accelerator("T");
   let vertical = CustomMenuItem::new("vertical".to_string(), "縦分割").accelerator("D");
   let limit = CustomMenuItem::new("limit".to_string(), "表示制限 (500行)");
-  let view = Submenu::new("表示", Menu::new()
+  let merge_wrapped = CustomMenuItem::new("merge_wrapped".to_string(), "折り返し行を結合");
+  let mut view = Menu::new()
     .add_item(all)
     .add_item(public)
     .add_item(private)
     .add_item(team)
     .add_item(club)
     .add_item(system)
-    .add_item(server)
+    .add_item(server);
+  for (i, channel) in channels.iter().enumerate().skip(Channel::VIEWS.len()) {
+    view = view.add_item(CustomMenuItem::new(format!("view{}", i), channel.name()));
+  }
+  let view = Submenu::new("表示", view
     .add_native_item(MenuItem::Separator)
     .add_item(exp)
     .add_native_item(MenuItem::Separator)
     .add_submenu(auto_scroll)
     .add_item(verbose)
     .add_item(vertical)
-    .add_item(limit));
+    .add_item(limit)
+    .add_item(merge_wrapped));
   let about = CustomMenuItem::new("about".to_string(), "Neosについて...");
   let help = Submenu::new("ヘルプ", Menu::new().add_item(about));
   let menu = Menu::new().add_submenu(file).add_submenu(view).add_submenu(help);
   Builder::default()
-    .setup(|app| {
+    .setup(move |app| {
+      if let Some(error) = config_error {
+        tauri::api::dialog::message(app.get_window("main").as_ref(), "設定エラー", format!("{}\n既定の設定で起動します。", error));
+      }
       let state = app.state() as tauri::State<Mutex<App>>;
       let mut state = state.lock().unwrap();
       let mut store = StoreBuilder::new(app.handle(), STORE_NAME.parse()?).build();
@@ -81,6 +143,9 @@ fn main() {
           app.get_window("main").unwrap().menu_handle().get_item("vertical").set_selected(state.vertical)?;
           state.limit = store.get("limit").unwrap_or(&json!(state.limit)).as_bool().unwrap();
           app.get_window("main").unwrap().menu_handle().get_item("limit").set_selected(state.limit)?;
+          let merge_wrapped = store.get("merge_wrapped").unwrap_or(&json!(state.merge_wrapped())).as_bool().unwrap();
+          state.set_merge_wrapped(merge_wrapped)?;
+          app.get_window("main").unwrap().menu_handle().get_item("merge_wrapped").set_selected(state.merge_wrapped())?;
         }
         _ => {
           for i in 0..state.views.len() {
@@ -97,30 +162,122 @@ fn main() {
           app.get_window("main").unwrap().menu_handle().get_item("vertical").set_selected(state.vertical)?;
           store.insert("limit".to_string(), json!(state.limit))?;
           app.get_window("main").unwrap().menu_handle().get_item("limit").set_selected(state.limit)?;
+          store.insert("merge_wrapped".to_string(), json!(state.merge_wrapped()))?;
+          app.get_window("main").unwrap().menu_handle().get_item("merge_wrapped").set_selected(state.merge_wrapped())?;
+          store.save()?;
+        }
+      }
+      match store.get("backfill").and_then(|backfill| serde_json::from_value(backfill.clone()).ok()) {
+        Some(backfill) => state.backfill = backfill,
+        _ => {
+          store.insert("backfill".to_string(), json!(state.backfill))?;
           store.save()?;
         }
       }
+      let (lines, minutes) = backfill_windows(&store);
+      store.insert("backfill_lines".to_string(), json!(lines))?;
+      store.insert("backfill_minutes".to_string(), json!(minutes))?;
+      store.save()?;
+      state.backfill = match state.backfill {
+        Backfill::Lines(_) => Backfill::Lines(lines),
+        Backfill::Minutes(_) => Backfill::Minutes(minutes),
+        backfill => backfill,
+      };
+      app.get_window("main").unwrap().menu_handle().get_item("backfill_lines").set_title(format!("最後の{}行", lines))?;
+      app.get_window("main").unwrap().menu_handle().get_item("backfill_minutes").set_title(format!("最後の{}分", minutes))?;
+      select_backfill(&app.get_window("main").unwrap().menu_handle(), state.backfill)?;
+      // Every message read is kept here, so what the channel buffers drop is
+      // not lost.
+      if let Some(dir) = app.path_resolver().app_data_dir() {
+        match Archive::open(&dir.join(DATABASE_NAME)) {
+          Ok(archive) => state.set_archive(Some(archive)),
+          Err(e) => tauri::api::dialog::message(app.get_window("main").as_ref(), "アーカイブ", format!("{:#}", e)),
+        }
+      }
+      match store.get("log_dir").and_then(|dir| dir.as_str()) {
+        Some(dir) => state.set_log_dir(PathBuf::from(dir))?,
+        _ => {
+          // Only a folder that holds chat logs is kept; otherwise the search
+          // runs again on the next start.
+          if let Some(dir) = discover_log_dirs().into_iter().next() {
+            state.set_log_dir(dir)?;
+            store.insert("log_dir".to_string(), json!(state.log_dir()))?;
+            store.save()?;
+          }
+        }
+      }
+      let sources: Vec<SourceConfig> = store.get("sources").and_then(|sources| serde_json::from_value(sources.clone()).ok()).unwrap_or_default();
+      for source in sources {
+        state.add_source(&source.name, source.log_dir, Box::new(FsLogSource::new()))?;
+      }
+      let (tx, rx) = mpsc::channel();
+      let mut watcher = LogWatcher::new(tx)?;
+      if let Err(e) = watcher.watch(&log_dirs(&state)) {
+        tauri::api::dialog::message(app.get_window("main").as_ref(), "ログフォルダ", format!("{:#}", e));
+      }
+      app.manage(Mutex::new(watcher));
       let app_handle = app.handle();
       thread::spawn(move || {
+        let mut wait = Some(Duration::ZERO);
+        let mut failing = false;
         loop {
-          thread::sleep(Duration::from_millis(500));
-          let utc = Utc::now().naive_utc();
-          let jst = Tokyo.from_utc_datetime(&utc);
-          let path = format!("C:\\Nexon\\TalesWeaver\\ChatLog\\TWChatLog_{}_{:>02}_{:>02}.html", jst.year(), jst.month(), jst.day());
-          let path = Path::new(&path);
+          // Sleeps until a file changes, or until the App has to read anyway,
+          // e.g. at midnight or to let go of a held wrapped line.
+          match wait {
+            Some(wait) => {
+              let _ = rx.recv_timeout(wait);
+            }
+            _ => {
+              let _ = rx.recv();
+            }
+          }
+          while rx.try_recv().is_ok() {}
+          let watcher = app_handle.state() as tauri::State<Mutex<LogWatcher>>;
+          let mut watcher = watcher.lock().unwrap();
+          if let Err(e) = watcher.retry() {
+            tauri::api::dialog::message(app_handle.get_window("main").as_ref(), "ログフォルダ", format!("{:#}", e));
+          }
+          let waiting = watcher.is_waiting();
+          drop(watcher);
           let state = app_handle.state() as tauri::State<Mutex<App>>;
           let mut app = state.lock().unwrap();
-          let result = app.read_log(path, utc);
-          let exp = app.calc_exp(utc);
-          app_handle.emit_all("exp", exp).unwrap();
-          if let Ok(ReadStatus::Unchanged) = result {
-            continue;
-          } else if let Err(_) = result {
-            app_handle.get_window("main").unwrap().emit_all("error", "").unwrap();
-            panic!("App::read_log error.");
+          let last_seq = app.last_seq();
+          let result = app.read_logs();
+          let retry = waiting || app.is_failing() || result.is_err();
+          wait = match (app.next_wake(), retry) {
+            (Some(wake), true) => Some(wake.min(RETRY_INTERVAL)),
+            (None, true) => Some(RETRY_INTERVAL),
+            (wake, false) => wake,
+          };
+          for error in app.take_archive_errors() {
+            app_handle.emit_all("archive_error", error).unwrap();
+          }
+          for error in app.take_source_errors() {
+            app_handle.emit_all("error", error).unwrap();
+          }
+          // A failed read is reported once and tried again on the next wake.
+          match result {
+            Err(e) if !failing => {
+              failing = true;
+              app_handle.emit_all("error", format!("{:#}", e)).unwrap();
+            }
+            Err(_) => (),
+            Ok(_) => failing = false,
+          }
+          // Sent even when every new message is filtered out, so the
+          // frontend can tell a lost event from one it never needed.
+          if app.last_seq() != last_seq {
+            app_handle.emit_all("messages_appended", messages_since(&app, last_seq)).unwrap();
           }
-          let messages = app.get_messages();
-          app_handle.emit_all("read", messages).unwrap();
+        }
+      });
+      let app_handle = app.handle();
+      thread::spawn(move || {
+        loop {
+          thread::sleep(Duration::from_secs(1));
+          let state = app_handle.state() as tauri::State<Mutex<App>>;
+          let exp = state.lock().unwrap().calc_exp();
+          app_handle.emit_all("exp", exp).unwrap();
         }
       });
       for i in 0..state.views.len() {
@@ -164,6 +321,37 @@ fn main() {
       let mut store = StoreBuilder::new(event.window().app_handle(), STORE_NAME.parse().unwrap()).build();
       store.load().unwrap();
       match event.menu_item_id() {
+        "log_dir" => {
+          let app_handle = event.window().app_handle();
+          FileDialogBuilder::new().set_directory(live_log_dir(&app, event.window().state::<Live>().inner())).pick_folder(move |dir| {
+            if let Some(dir) = dir {
+              if let Err(e) = set_log_dir(app_handle.clone(), app_handle.state(), dir.display().to_string()) {
+                tauri::api::dialog::message(app_handle.get_window("main").as_ref(), "ログフォルダ", e);
+              }
+            }
+          });
+        }
+        "replay_1" | "replay_10" | "replay_instant" => {
+          let speed = REPLAY_SPEEDS.iter().find(|(id, _, _)| *id == event.menu_item_id()).unwrap().2;
+          let app_handle = event.window().app_handle();
+          FileDialogBuilder::new().set_directory(live_log_dir(&app, event.window().state::<Live>().inner())).add_filter("チャットログ", &["html"]).pick_file(move |path| {
+            if let Some(path) = path {
+              if let Err(e) = start_replay(app_handle.clone(), app_handle.state(), app_handle.state(), path.display().to_string(), speed) {
+                tauri::api::dialog::message(app_handle.get_window("main").as_ref(), "ログを再生", e);
+              }
+            }
+          });
+        }
+        "replay_stop" => {
+          match restore_live(&mut app, event.window().state::<Live>().inner()) {
+            Ok(true) => {
+              wake_tail(&event.window().app_handle());
+              event.window().emit_all("read", MessagesRead::new(&app)).unwrap();
+            }
+            Ok(false) => (),
+            Err(e) => tauri::api::dialog::message(Some(event.window()), "再生を停止", e),
+          }
+        }
         "exit" => event.window().close().unwrap(),
         "exp" => {
           app.exp = !app.exp;
@@ -192,6 +380,31 @@ fn main() {
           store.save().unwrap();
           event.window().menu_handle().get_item(event.menu_item_id()).set_selected(app.limit).unwrap();
         }
+        "merge_wrapped" => {
+          let last_seq = app.last_seq();
+          let merge_wrapped = !app.merge_wrapped();
+          if let Err(e) = app.set_merge_wrapped(merge_wrapped) {
+            tauri::api::dialog::message(Some(event.window()), "折り返し行を結合", format!("{:#}", e));
+          }
+          if app.last_seq() != last_seq {
+            event.window().emit_all("messages_appended", messages_since(&app, last_seq)).unwrap();
+          }
+          store.insert("merge_wrapped".to_string(), json!(app.merge_wrapped())).unwrap();
+          store.save().unwrap();
+          event.window().menu_handle().get_item(event.menu_item_id()).set_selected(app.merge_wrapped()).unwrap();
+        }
+        "backfill_off" | "backfill_all" | "backfill_lines" | "backfill_minutes" => {
+          let (lines, minutes) = backfill_windows(&store);
+          app.backfill = match event.menu_item_id() {
+            "backfill_all" => Backfill::All,
+            "backfill_lines" => Backfill::Lines(lines),
+            "backfill_minutes" => Backfill::Minutes(minutes),
+            _ => Backfill::Off,
+          };
+          store.insert("backfill".to_string(), json!(app.backfill)).unwrap();
+          store.save().unwrap();
+          select_backfill(&event.window().menu_handle(), app.backfill).unwrap();
+        }
         "about" => event.window().emit_all("about", "").unwrap(),
         _ => ()
       }
@@ -213,13 +426,29 @@ fn main() {
         event.window().emit_all(format!("auto_scroll{}", i).as_str(), app.auto_scroll[i]).unwrap();
       }
     })
-    .manage(Mutex::new(App::new()))
-    .invoke_handler(tauri::generate_handler![get_state, get_store_name])
+    .manage(Mutex::new(state))
+    .manage(Mutex::new(None::<App>))
+    .manage(Live(Mutex::new(None)))
+    .invoke_handler(tauri::generate_handler![get_state, get_store_name, get_quarantine, get_channels, get_messages_since, get_messages_range, get_whisper_partners, get_log_dir, get_log_dir_candidates, set_log_dir, list_log_files, open_log_file, search_log_file, get_log_file_exp, close_log_file, get_sources, add_source, remove_source, set_source_filter, start_replay, stop_replay])
     .plugin(tauri_plugin_store::Builder::default().build())
-    .run(tauri::generate_context!())
+    .run(context)
     .expect("error while running application");
 }
 
+fn backfill_windows<R: tauri::Runtime>(store: &Store<R>) -> (usize, i64) {
+  let lines = store.get("backfill_lines").and_then(|lines| lines.as_u64()).map(|lines| lines as usize).unwrap_or(DEFAULT_BACKFILL_LINES);
+  let minutes = store.get("backfill_minutes").and_then(|minutes| minutes.as_i64()).unwrap_or(DEFAULT_BACKFILL_MINUTES);
+  (lines, minutes)
+}
+
+fn select_backfill(menu: &MenuHandle, backfill: Backfill) -> tauri::Result<()> {
+  menu.get_item("backfill_off").set_selected(matches!(backfill, Backfill::Off))?;
+  menu.get_item("backfill_all").set_selected(matches!(backfill, Backfill::All))?;
+  menu.get_item("backfill_lines").set_selected(matches!(backfill, Backfill::Lines(_)))?;
+  menu.get_item("backfill_minutes").set_selected(matches!(backfill, Backfill::Minutes(_)))?;
+  Ok(())
+}
+
 #[derive(Serialize, Deserialize)]
 struct State {
   views: Vec<bool>,
@@ -229,6 +458,41 @@ struct State {
   vertical: bool,
 }
 
+// Only the messages read after `since` up to `last`. The frontend puts each
+// one in the all view and the view of its channel, and resyncs when `since`
+// is not where it got to.
+#[derive(Serialize, Deserialize)]
+struct MessagesAppended {
+  since: Option<u64>,
+  last: Option<u64>,
+  messages: Vec<ChatMessage>,
+  limit: Option<usize>,
+}
+
+// Every view afresh, e.g. when a replay takes the place of the live App and the
+// numbering starts over. `last` is where the frontend resyncs from.
+#[derive(Serialize, Deserialize)]
+struct MessagesRead {
+  logs: Vec<ChannelLog>,
+  last: Option<u64>,
+}
+
+impl MessagesRead {
+  fn new(state: &App) -> Self {
+    MessagesRead { logs: state.get_messages(), last: state.last_seq() }
+  }
+}
+
+fn messages_since(state: &App, seq: Option<u64>) -> MessagesAppended {
+  MessagesAppended { since: seq, last: state.last_seq(), messages: state.messages_after(seq), limit: state.display_limit() }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelInfo {
+  channel: Channel,
+  name: String,
+}
+
 #[tauri::command]
 fn get_state(state: tauri::State<Mutex<App>>) -> Result<State, String> {
   let state = state.lock().unwrap();
@@ -244,3 +508,227 @@ fn get_state(state: tauri::State<Mutex<App>>) -> Result<State, String> {
 fn get_store_name() -> String {
   STORE_NAME.to_string()
 }
+
+#[tauri::command]
+fn get_quarantine(state: tauri::State<Mutex<App>>) -> QuarantineReport {
+  let state = state.lock().unwrap();
+  state.get_quarantine()
+}
+
+#[tauri::command]
+fn get_channels(state: tauri::State<Mutex<App>>) -> Vec<ChannelInfo> {
+  let state = state.lock().unwrap();
+  state.channels().into_iter().map(|channel| ChannelInfo { name: channel.name().to_string(), channel }).collect()
+}
+
+#[tauri::command]
+fn get_messages_since(state: tauri::State<Mutex<App>>, seq: Option<u64>) -> MessagesAppended {
+  let state = state.lock().unwrap();
+  messages_since(&state, seq)
+}
+
+#[tauri::command]
+fn get_messages_range(state: tauri::State<Mutex<App>>, channel: Channel, before_seq: Option<u64>, limit: usize) -> Vec<ChatMessage> {
+  let state = state.lock().unwrap();
+  state.get_messages_range(&channel, before_seq, limit)
+}
+
+#[tauri::command]
+fn get_whisper_partners(state: tauri::State<Mutex<App>>) -> Vec<WhisperPartner> {
+  let state = state.lock().unwrap();
+  state.whisper_partners()
+}
+
+#[tauri::command]
+fn get_log_dir(state: tauri::State<Mutex<App>>, live: tauri::State<Live>) -> PathBuf {
+  let state = state.lock().unwrap();
+  live_log_dir(&state, &live)
+}
+
+#[tauri::command]
+fn get_log_dir_candidates() -> Vec<PathBuf> {
+  discover_log_dirs()
+}
+
+#[tauri::command]
+fn set_log_dir(app_handle: AppHandle, state: tauri::State<Mutex<App>>, dir: String) -> Result<(), String> {
+  let dir = PathBuf::from(dir);
+  validate_log_dir(&dir).map_err(|e| format!("{:#}", e))?;
+  let mut state = state.lock().unwrap();
+  state.set_log_dir(dir.clone()).map_err(|e| format!("{:#}", e))?;
+  let mut store = StoreBuilder::new(app_handle.clone(), STORE_NAME.parse().unwrap()).build();
+  let _ = store.load();
+  store.insert("log_dir".to_string(), json!(dir)).map_err(|e| e.to_string())?;
+  store.save().map_err(|e| e.to_string())?;
+  let watcher = app_handle.state() as tauri::State<Mutex<LogWatcher>>;
+  let mut watcher = watcher.lock().unwrap();
+  let watched = watcher.watch(&log_dirs(&state));
+  watcher.wake();
+  watched.map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn list_log_files(state: tauri::State<Mutex<App>>, live: tauri::State<Live>) -> Result<Vec<LogFileInfo>, String> {
+  let log_dir = live_log_dir(&state.lock().unwrap(), &live);
+  list_logs(&log_dir).map_err(|e| format!("{:#}", e))
+}
+
+// Past logs are loaded into their own App so browsing them leaves the live
+// tail alone.
+#[tauri::command]
+fn open_log_file(state: tauri::State<Mutex<App>>, history: tauri::State<Mutex<Option<App>>>, live: tauri::State<Live>, name: String) -> Result<Vec<ChannelLog>, String> {
+  if log_file_date(&name).is_none() {
+    return Err(format!("{} はチャットログではありません。", name));
+  }
+  let mut view = App::new();
+  {
+    let state = state.lock().unwrap();
+    view.set_color_map(state.color_map().clone());
+    view.set_unbounded();
+    view.set_merge_wrapped(state.merge_wrapped()).map_err(|e| format!("{:#}", e))?;
+    view.limit = false;
+    view.load_log(&live_log_dir(&state, &live).join(&name)).map_err(|e| format!("{:#}", e))?;
+  }
+  let messages = view.get_messages();
+  *history.lock().unwrap() = Some(view);
+  Ok(messages)
+}
+
+#[tauri::command]
+fn search_log_file(history: tauri::State<Mutex<Option<App>>>, query: String, channel: Channel) -> Vec<ChatMessage> {
+  match history.lock().unwrap().as_ref() {
+    Some(view) => view.search(&query, &channel),
+    _ => Vec::new(),
+  }
+}
+
+#[tauri::command]
+fn get_log_file_exp(history: tauri::State<Mutex<Option<App>>>) -> Option<ExpSummary> {
+  history.lock().unwrap().as_ref().map(|view| view.exp_summary())
+}
+
+#[tauri::command]
+fn close_log_file(history: tauri::State<Mutex<Option<App>>>) {
+  *history.lock().unwrap() = None;
+}
+
+fn wake_tail(app_handle: &AppHandle) {
+  let watcher = app_handle.state() as tauri::State<Mutex<LogWatcher>>;
+  watcher.lock().unwrap().wake();
+}
+
+fn log_dirs(state: &App) -> Vec<PathBuf> {
+  state.sources().into_iter().map(|source| source.log_dir).collect()
+}
+
+// The primary source is saved as "log_dir", the rest as "sources".
+fn save_sources(app_handle: AppHandle, state: &App) -> Result<(), String> {
+  let mut store = StoreBuilder::new(app_handle.clone(), STORE_NAME.parse().unwrap()).build();
+  let _ = store.load();
+  store.insert("sources".to_string(), json!(state.sources()[1..])).map_err(|e| e.to_string())?;
+  store.save().map_err(|e| e.to_string())?;
+  app_handle.emit_all("sources", state.sources()).map_err(|e| e.to_string())?;
+  let watcher = app_handle.state() as tauri::State<Mutex<LogWatcher>>;
+  let mut watcher = watcher.lock().unwrap();
+  let watched = watcher.watch(&log_dirs(state));
+  watcher.wake();
+  watched.map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn get_sources(state: tauri::State<Mutex<App>>, live: tauri::State<Live>) -> Vec<SourceConfig> {
+  let state = state.lock().unwrap();
+  match live.0.lock().unwrap().as_ref() {
+    Some(app) => app.sources(),
+    _ => state.sources(),
+  }
+}
+
+#[tauri::command]
+fn add_source(app_handle: AppHandle, state: tauri::State<Mutex<App>>, name: String, dir: String) -> Result<(), String> {
+  let dir = PathBuf::from(dir);
+  validate_log_dir(&dir).map_err(|e| format!("{:#}", e))?;
+  let mut state = state.lock().unwrap();
+  state.add_source(&name, dir, Box::new(FsLogSource::new())).map_err(|e| format!("{:#}", e))?;
+  save_sources(app_handle, &state)
+}
+
+#[tauri::command]
+fn remove_source(app_handle: AppHandle, state: tauri::State<Mutex<App>>, name: String) -> Result<(), String> {
+  let mut state = state.lock().unwrap();
+  state.remove_source(&name).map_err(|e| format!("{:#}", e))?;
+  save_sources(app_handle, &state)
+}
+
+#[tauri::command]
+fn set_source_filter(state: tauri::State<Mutex<App>>, name: Option<String>) -> Vec<ChannelLog> {
+  let mut state = state.lock().unwrap();
+  state.source_filter = name;
+  state.get_messages()
+}
+
+// The live App waits here while a replay runs in its place, so the tail and
+// exp threads drive the replay with the same events.
+struct Live(Mutex<Option<App>>);
+
+fn copy_settings(from: &App, to: &mut App) -> Result<(), String> {
+  to.views = from.views.clone();
+  to.exp = from.exp;
+  to.auto_scroll = from.auto_scroll.clone();
+  to.verbose = from.verbose;
+  to.vertical = from.vertical;
+  to.limit = from.limit;
+  to.set_merge_wrapped(from.merge_wrapped()).map_err(|e| format!("{:#}", e))
+}
+
+// The user's log folder, even while a replay stands in for the live App.
+fn live_log_dir(state: &App, live: &Live) -> PathBuf {
+  match live.0.lock().unwrap().as_ref() {
+    Some(app) => app.log_dir().to_path_buf(),
+    _ => state.log_dir().to_path_buf(),
+  }
+}
+
+// The replay keeps running if the live App cannot take its settings back.
+fn restore_live(state: &mut App, live: &Live) -> Result<bool, String> {
+  let mut live = live.0.lock().unwrap();
+  match live.take() {
+    Some(mut app) => {
+      if let Err(e) = copy_settings(state, &mut app) {
+        *live = Some(app);
+        return Err(e);
+      }
+      *state = app;
+      Ok(true)
+    }
+    _ => Ok(false),
+  }
+}
+
+#[tauri::command]
+fn start_replay(app_handle: AppHandle, state: tauri::State<Mutex<App>>, live: tauri::State<Live>, path: String, speed: ReplaySpeed) -> Result<(), String> {
+  let source = ReplayLogSource::load(Path::new(&path), speed, Box::new(SystemClock)).map_err(|e| format!("{:#}", e))?;
+  let mut state = state.lock().unwrap();
+  let mut replay = App::new();
+  replay.set_color_map(state.color_map().clone());
+  copy_settings(&state, &mut replay)?;
+  replay.replay(source);
+  let app = std::mem::replace(&mut *state, replay);
+  let mut live = live.0.lock().unwrap();
+  if live.is_none() {
+    *live = Some(app);
+  }
+  wake_tail(&app_handle);
+  app_handle.emit_all("read", MessagesRead::new(&state)).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+fn stop_replay(app_handle: AppHandle, state: tauri::State<Mutex<App>>, live: tauri::State<Live>) -> Result<(), String> {
+  let mut state = state.lock().unwrap();
+  if restore_live(&mut state, &live)? {
+    wake_tail(&app_handle);
+    app_handle.emit_all("read", MessagesRead::new(&state)).map_err(|e| e.to_string())?;
+  }
+  Ok(())
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+  All,
+  Public,
+  Whisper,
+  Team,
+  Club,
+  System,
+  Shout,
+  Custom(String),
+  Unknown,
+}
+
+impl Channel {
+  pub const VIEWS: [Channel; 7] = [
+    Channel::All,
+    Channel::Public,
+    Channel::Whisper,
+    Channel::Team,
+    Channel::Club,
+    Channel::System,
+    Channel::Shout,
+  ];
+
+  pub fn from_name(name: &str) -> Option<Channel> {
+    match name {
+      "public" => Some(Channel::Public),
+      "whisper" => Some(Channel::Whisper),
+      "team" => Some(Channel::Team),
+      "club" => Some(Channel::Club),
+      "system" => Some(Channel::System),
+      "shout" => Some(Channel::Shout),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    match self {
+      Channel::All => "全体",
+      Channel::Public => "一般",
+      Channel::Whisper => "耳打ち",
+      Channel::Team => "チーム",
+      Channel::Club => "クラブ",
+      Channel::System => "システム",
+      Channel::Shout => "叫び",
+      Channel::Custom(name) => name,
+      Channel::Unknown => "不明",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhisperDirection {
+  Incoming,
+  // Not read from logs until the marker is confirmed; see speaker.rs.
+  Outgoing,
+  Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Whisper {
+  pub direction: WhisperDirection,
+  pub partner: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhisperPartner {
+  pub name: String,
+  pub last_time: DateTime<FixedOffset>,
+  pub last_direction: WhisperDirection,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+  pub seq: u64,
+  pub source: String,
+  pub speaker: Option<String>,
+  pub whisper: Option<Whisper>,
+  pub text: String,
+  pub color: String,
+  pub channel: Channel,
+  pub time: DateTime<FixedOffset>,
+  pub backfilled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpSummary {
+  pub total: i64,
+  pub count: u64,
+  pub first_time: Option<DateTime<FixedOffset>>,
+  pub last_time: Option<DateTime<FixedOffset>>,
+  pub per_hour: i64,
+}
+
+pub const DEFAULT_CAPACITY: usize = 10000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelLog {
+  pub channel: Channel,
+  pub messages: VecDeque<ChatMessage>,
+  pub updated: bool,
+}
+
+impl ChannelLog {
+  pub fn new(channel: Channel) -> Self {
+    ChannelLog { channel, messages: VecDeque::new(), updated: false }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quarantined {
+  pub raw: String,
+  pub reason: String,
+  pub message: Option<ChatMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineReport {
+  pub count: u64,
+  pub lines: Vec<Quarantined>,
+}
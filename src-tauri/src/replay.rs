@@ -0,0 +1,187 @@
+use std::{fs, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{header::LogHeader, jst, log_file_date, Clock, LogSource, RawLine, SourceIdentity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaySpeed {
+  Times(u32),
+  Instant,
+}
+
+// Runs from the first message of a replayed file at the given speed of the
+// real clock. The day stays on the file's date even past midnight.
+#[derive(Debug, Clone)]
+pub struct ReplayClock {
+  real: Arc<Mutex<Box<dyn Clock>>>,
+  started: DateTime<Utc>,
+  start: DateTime<Utc>,
+  end: DateTime<Utc>,
+  date: NaiveDate,
+  speed: ReplaySpeed,
+}
+
+impl ReplayClock {
+  pub fn is_finished(&self) -> bool {
+    self.end <= self.now()
+  }
+}
+
+impl Clock for ReplayClock {
+  fn now(&self) -> DateTime<Utc> {
+    match self.speed {
+      ReplaySpeed::Times(times) => self.start + (self.real.lock().unwrap().now() - self.started) * times as i32,
+      ReplaySpeed::Instant => self.end,
+    }
+  }
+
+  fn today(&self) -> NaiveDate {
+    self.date
+  }
+}
+
+// Serves one finished log file, releasing each line once the replay clock
+// reaches its time. Untimed lines such as the header and wrapped lines go
+// out with the line before them.
+#[derive(Debug)]
+pub struct ReplayLogSource {
+  path: PathBuf,
+  content: Vec<u8>,
+  released: u64,
+  lines: Vec<(DateTime<Utc>, u64)>,
+  clock: ReplayClock,
+}
+
+impl ReplayLogSource {
+  pub fn load(path: &Path, speed: ReplaySpeed, real: Box<dyn Clock>) -> Result<Self> {
+    let content = fs::read(path).with_context(|| format!("{} を読み込めません。", path.display()))?;
+    ReplayLogSource::new(path, content, speed, real)
+  }
+
+  pub fn new(path: &Path, content: Vec<u8>, speed: ReplaySpeed, real: Box<dyn Clock>) -> Result<Self> {
+    let date = match LogHeader::read(&content) {
+      Some(header) => header.date,
+      _ => match path.file_name().and_then(|name| name.to_str()).and_then(log_file_date) {
+        Some(date) => date,
+        _ => bail!("{} の日付がわかりません。", path.display()),
+      },
+    };
+
+    let mut released = 0;
+    let mut lines: Vec<(DateTime<Utc>, u64)> = Vec::new();
+    let mut last_time: Option<DateTime<FixedOffset>> = None;
+    let mut offset = 0;
+    for line in content.split_inclusive(|&byte| byte == b'\n') {
+      offset += line.len() as u64;
+      let (text, _, _) = encoding_rs::SHIFT_JIS.decode(line);
+      let time = match RawLine::parse(text.trim_end_matches("\r\n")).and_then(|line| line.time_of_day()) {
+        Some(time) => time,
+        _ => {
+          match lines.last_mut() {
+            Some((_, end)) => *end = offset,
+            _ => released = offset,
+          }
+          continue;
+        }
+      };
+      let mut timestamp = jst(date, time);
+      if let Some(last_time) = last_time {
+        while timestamp < last_time - Duration::hours(12) {
+          timestamp += Duration::days(1);
+        }
+      }
+      last_time = Some(timestamp);
+      // A line never goes out before the one above it.
+      let time = match lines.last() {
+        Some((last, _)) => timestamp.with_timezone(&Utc).max(*last),
+        _ => timestamp.with_timezone(&Utc),
+      };
+      lines.push((time, offset));
+    }
+    if lines.is_empty() {
+      bail!("{} にメッセージがありません。", path.display());
+    }
+
+    let started = real.now();
+    let start = lines[0].0;
+    let end = lines[lines.len() - 1].0;
+    let clock = ReplayClock { real: Arc::new(Mutex::new(real)), started, start, end, date, speed };
+    Ok(ReplayLogSource { path: path.to_path_buf(), content, released, lines, clock })
+  }
+
+  pub fn clock(&self) -> ReplayClock {
+    self.clock.clone()
+  }
+
+  fn released(&self) -> u64 {
+    let now = self.clock.now();
+    match self.lines.partition_point(|(time, _)| *time <= now) {
+      0 => self.released,
+      i => self.lines[i - 1].1,
+    }
+  }
+}
+
+// A replay has only the one file, whatever path today's log would have.
+impl LogSource for ReplayLogSource {
+  fn exists(&self, _: &Path) -> bool {
+    true
+  }
+
+  fn open(&mut self, _: &Path) -> Result<()> {
+    Ok(())
+  }
+
+  fn close(&mut self) {}
+
+  fn size(&mut self) -> Result<u64> {
+    Ok(self.released())
+  }
+
+  fn read_from(&mut self, offset: u64, limit: u64) -> Result<Vec<u8>> {
+    let released = self.released();
+    let start = offset.min(released) as usize;
+    let end = offset.saturating_add(limit).min(released) as usize;
+    Ok(self.content[start..end].to_vec())
+  }
+
+  fn identity(&self, _: &Path) -> Result<SourceIdentity> {
+    Ok(SourceIdentity { path: self.path.clone(), file_id: None, created: None, header_date: LogHeader::read(&self.content).map(|header| header.date) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{testing::log_line, ManualClock, SystemClock};
+  use chrono::TimeZone;
+
+  #[test]
+  fn release_by_time() {
+    let header = b"<body bgcolor=\"black\">\r\n".to_vec();
+    let wrapped = b"<font size=\"2\" color=\"#ff64ff\">wrapped</font></br>\r\n".to_vec();
+    let content = [header.clone(), log_line("[23時 59分 58秒]", "one"), log_line("[23時 59分 59秒]", "two"), wrapped.clone(), log_line("[ 0時 0分 1秒]", "three")].concat();
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    let real = ManualClock::new(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+    let mut source = ReplayLogSource::new(path, content.clone(), ReplaySpeed::Times(2), Box::new(real.clone())).unwrap();
+    let clock = source.clock();
+    assert_eq!(clock.now(), Utc.with_ymd_and_hms(2024, 4, 20, 14, 59, 58).unwrap());
+    let size = source.size().unwrap();
+    assert_eq!(source.read_from(0, 1000).unwrap(), [header.clone(), log_line("[23時 59分 58秒]", "one")].concat());
+
+    real.advance(Duration::milliseconds(500));
+    assert_eq!(source.read_from(size, 1000).unwrap(), [log_line("[23時 59分 59秒]", "two"), wrapped].concat());
+
+    real.advance(Duration::milliseconds(1000));
+    assert_eq!(clock.now(), Utc.with_ymd_and_hms(2024, 4, 20, 15, 0, 1).unwrap());
+    assert_eq!(clock.today(), NaiveDate::from_ymd_opt(2024, 4, 20).unwrap());
+    assert!(clock.is_finished());
+    assert_eq!(source.size().unwrap(), content.len() as u64);
+
+    let source = ReplayLogSource::new(path, content, ReplaySpeed::Instant, Box::new(real)).unwrap();
+    assert!(source.clock().is_finished());
+    assert!(ReplayLogSource::new(path, header, ReplaySpeed::Instant, Box::new(SystemClock)).is_err());
+  }
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, fmt::Debug, fs::File, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::SystemTime};
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use crate::header::{LogHeader, HEADER_SIZE};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceConfig {
+  pub name: String,
+  pub log_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceIdentity {
+  pub path: PathBuf,
+  pub file_id: Option<u64>,
+  pub created: Option<SystemTime>,
+  pub header_date: Option<NaiveDate>,
+}
+
+impl SourceIdentity {
+  // A header still being written has no date yet, so the dates only tell
+  // two files apart once both are known.
+  pub fn same_file(&self, other: &SourceIdentity) -> bool {
+    self.path == other.path
+      && self.file_id == other.file_id
+      && self.created == other.created
+      && (self.header_date.is_none() || other.header_date.is_none() || self.header_date == other.header_date)
+  }
+}
+
+fn header_date(content: &[u8]) -> Option<NaiveDate> {
+  LogHeader::read(content).map(|header| header.date)
+}
+
+pub trait LogSource: Debug + Send {
+  fn exists(&self, path: &Path) -> bool;
+  fn open(&mut self, path: &Path) -> Result<()>;
+  fn close(&mut self);
+  fn size(&mut self) -> Result<u64>;
+  fn read_from(&mut self, offset: u64, limit: u64) -> Result<Vec<u8>>;
+  fn identity(&self, path: &Path) -> Result<SourceIdentity>;
+}
+
+#[derive(Debug, Default)]
+pub struct FsLogSource {
+  file: Option<(PathBuf, File)>,
+}
+
+impl FsLogSource {
+  pub fn new() -> Self {
+    FsLogSource { file: None }
+  }
+
+  fn file(&mut self) -> Result<&mut (PathBuf, File)> {
+    match self.file.as_mut() {
+      Some(file) => Ok(file),
+      _ => bail!("log file is not open."),
+    }
+  }
+}
+
+impl LogSource for FsLogSource {
+  fn exists(&self, path: &Path) -> bool {
+    path.is_file()
+  }
+
+  fn open(&mut self, path: &Path) -> Result<()> {
+    drop(self.file.take());
+    self.file = Some((path.to_path_buf(), File::open(path)?));
+    Ok(())
+  }
+
+  fn close(&mut self) {
+    drop(self.file.take());
+  }
+
+  fn size(&mut self) -> Result<u64> {
+    let (path, _) = self.file()?;
+    Ok(std::fs::metadata(path)?.len())
+  }
+
+  fn read_from(&mut self, offset: u64, limit: u64) -> Result<Vec<u8>> {
+    let (_, file) = self.file()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut content = Vec::with_capacity(limit as usize);
+    file.take(limit).read_to_end(&mut content)?;
+    Ok(content)
+  }
+
+  fn identity(&self, path: &Path) -> Result<SourceIdentity> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut head = Vec::new();
+    (&mut file).take(HEADER_SIZE).read_to_end(&mut head)?;
+    Ok(SourceIdentity { path: path.to_path_buf(), file_id: file_id(&file, &metadata), created: metadata.created().ok(), header_date: header_date(&head) })
+  }
+}
+
+#[cfg(unix)]
+fn file_id(_: &File, metadata: &std::fs::Metadata) -> Option<u64> {
+  use std::os::unix::fs::MetadataExt;
+  Some(metadata.ino())
+}
+
+// The file index is unique within the volume and kept until the file is
+// deleted, like an inode number.
+#[cfg(windows)]
+fn file_id(file: &File, _: &std::fs::Metadata) -> Option<u64> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::{Foundation::HANDLE, Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION}};
+  // SAFETY: the handle belongs to an open file and the struct is plain data.
+  let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+  match unsafe { GetFileInformationByHandle(file.as_raw_handle() as HANDLE, &mut info) } {
+    0 => None,
+    _ => Some(((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64),
+  }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn file_id(_: &File, _: &std::fs::Metadata) -> Option<u64> {
+  None
+}
+
+#[derive(Debug, Clone)]
+struct MemoryFile {
+  content: Vec<u8>,
+  file_id: u64,
+}
+
+// Clones share the same files, so a test can keep one handle to write with
+// while the App reads through another.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLogSource {
+  files: Arc<Mutex<HashMap<PathBuf, MemoryFile>>>,
+  next_id: Arc<Mutex<u64>>,
+  path: Option<PathBuf>,
+}
+
+impl MemoryLogSource {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn create(&self, path: &Path, content: &[u8]) {
+    let mut next_id = self.next_id.lock().unwrap();
+    *next_id += 1;
+    self.files.lock().unwrap().insert(path.to_path_buf(), MemoryFile { content: content.to_vec(), file_id: *next_id });
+  }
+
+  pub fn append(&self, path: &Path, content: &[u8]) {
+    let mut files = self.files.lock().unwrap();
+    if let Some(file) = files.get_mut(path) {
+      file.content.extend_from_slice(content);
+      return;
+    }
+    drop(files);
+    self.create(path, content);
+  }
+
+  pub fn truncate(&self, path: &Path, len: usize) {
+    if let Some(file) = self.files.lock().unwrap().get_mut(path) {
+      file.content.truncate(len);
+    }
+  }
+
+  pub fn remove(&self, path: &Path) {
+    self.files.lock().unwrap().remove(path);
+  }
+
+  fn with_file<T>(&self, f: impl FnOnce(&MemoryFile) -> T) -> Result<T> {
+    let path = match self.path.as_ref() {
+      Some(path) => path,
+      _ => bail!("log file is not open."),
+    };
+    match self.files.lock().unwrap().get(path) {
+      Some(file) => Ok(f(file)),
+      _ => bail!("{} does not exist.", path.display()),
+    }
+  }
+}
+
+impl LogSource for MemoryLogSource {
+  fn exists(&self, path: &Path) -> bool {
+    self.files.lock().unwrap().contains_key(path)
+  }
+
+  fn open(&mut self, path: &Path) -> Result<()> {
+    if !self.exists(path) {
+      bail!("{} does not exist.", path.display());
+    }
+    self.path = Some(path.to_path_buf());
+    Ok(())
+  }
+
+  fn close(&mut self) {
+    self.path = None;
+  }
+
+  fn size(&mut self) -> Result<u64> {
+    self.with_file(|file| file.content.len() as u64)
+  }
+
+  fn read_from(&mut self, offset: u64, limit: u64) -> Result<Vec<u8>> {
+    self.with_file(|file| {
+      let start = (offset as usize).min(file.content.len());
+      let end = offset.saturating_add(limit).min(file.content.len() as u64) as usize;
+      file.content[start..end].to_vec()
+    })
+  }
+
+  fn identity(&self, path: &Path) -> Result<SourceIdentity> {
+    match self.files.lock().unwrap().get(path) {
+      Some(file) => Ok(SourceIdentity { path: path.to_path_buf(), file_id: Some(file.file_id), created: None, header_date: header_date(&file.content) }),
+      _ => bail!("{} does not exist.", path.display()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn memory_source() {
+    let path = Path::new("TWChatLog_2024_04_20.html");
+    let writer = MemoryLogSource::new();
+    let mut source = writer.clone();
+    assert!(!source.exists(path));
+    assert!(source.open(path).is_err());
+
+    writer.append(path, b"0123");
+    source.open(path).unwrap();
+    let identity = source.identity(path).unwrap();
+    writer.append(path, b"4567");
+    assert_eq!(source.size().unwrap(), 8);
+    assert_eq!(source.read_from(2, 4).unwrap(), b"2345");
+    assert_eq!(source.read_from(6, 4).unwrap(), b"67");
+    assert_eq!(source.read_from(10, 4).unwrap(), b"");
+
+    writer.truncate(path, 2);
+    assert_eq!(source.size().unwrap(), 2);
+    assert_eq!(source.identity(path).unwrap(), identity);
+    writer.create(path, b"new");
+    assert_ne!(source.identity(path).unwrap(), identity);
+    writer.remove(path);
+    assert!(source.size().is_err());
+  }
+
+  #[test]
+  fn fs_source() {
+    let path = &crate::testing::fixture("TWChatLog_2024_04_20.html");
+    let mut source = FsLogSource::new();
+    assert!(source.size().is_err());
+    source.open(path).unwrap();
+    let len = std::fs::metadata(path).unwrap().len();
+    assert_eq!(source.size().unwrap(), len);
+    assert_eq!(source.read_from(0, 5).unwrap(), b"<body");
+    assert_eq!(source.read_from(len - 7, 10).unwrap(), b"</br>\r\n");
+    let identity = source.identity(path).unwrap();
+    assert_eq!(identity.path, *path);
+    assert!(identity.file_id.is_some());
+    assert_eq!(identity.header_date, NaiveDate::from_ymd_opt(2024, 4, 20));
+  }
+
+  #[test]
+  fn same_file() {
+    let identity = SourceIdentity { path: PathBuf::from("TWChatLog_2024_04_20.html"), file_id: Some(1), created: None, header_date: None };
+    let dated = SourceIdentity { header_date: NaiveDate::from_ymd_opt(2024, 4, 20), ..identity.clone() };
+    assert!(identity.same_file(&dated));
+    assert!(dated.same_file(&identity));
+    // The same file index reused for a new day's file.
+    assert!(!dated.same_file(&SourceIdentity { header_date: NaiveDate::from_ymd_opt(2024, 4, 21), ..identity.clone() }));
+    assert!(!dated.same_file(&SourceIdentity { file_id: Some(2), ..dated.clone() }));
+  }
+}
@@ -0,0 +1,54 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use crate::{Channel, Whisper, WhisperDirection};
+
+const SHOUT_PREFIX: &str = "叫ぶ : ";
+// Whispers we send are thought to be logged as "相手へ : text", but no real log
+// has shown one yet, and a name may end in へ too. Such a line is left with an
+// unknown direction rather than guessed.
+const OUTGOING_SUFFIX: &str = "へ";
+
+static SPEAKER: Lazy<Regex> = Lazy::new(|| Regex::new(r##"^([^\s:：]{1,16}) : (.*)$"##).unwrap());
+
+pub fn split_speaker(channel: &Channel, text: &str) -> Option<(String, String)> {
+  let text = match channel {
+    Channel::Public | Channel::Whisper | Channel::Team | Channel::Club => text,
+    Channel::Shout => text.strip_prefix(SHOUT_PREFIX).unwrap_or(text),
+    _ => return None,
+  };
+  let captures = SPEAKER.captures(text)?;
+  Some((captures[1].to_string(), captures[2].to_string()))
+}
+
+pub fn whisper(speaker: &str) -> Whisper {
+  let direction = if speaker.ends_with(OUTGOING_SUFFIX) { WhisperDirection::Unknown } else { WhisperDirection::Incoming };
+  Whisper { direction, partner: speaker.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_player_chat() {
+    assert_eq!(split_speaker(&Channel::Public, "JADEN : てｓｔ"), Some(("JADEN".to_string(), "てｓｔ".to_string())));
+    assert_eq!(split_speaker(&Channel::Team, "ジェイデン : a : b"), Some(("ジェイデン".to_string(), "a : b".to_string())));
+    assert_eq!(split_speaker(&Channel::Club, "JADEN : "), Some(("JADEN".to_string(), "".to_string())));
+    assert_eq!(split_speaker(&Channel::Shout, "叫ぶ : JADEN : 募集"), Some(("JADEN".to_string(), "募集".to_string())));
+  }
+
+  #[test]
+  fn whisper_direction() {
+    assert_eq!(whisper("JADEN"), Whisper { direction: WhisperDirection::Incoming, partner: "JADEN".to_string() });
+    assert_eq!(whisper("JADENへ"), Whisper { direction: WhisperDirection::Unknown, partner: "JADENへ".to_string() });
+    assert_eq!(whisper("へ"), Whisper { direction: WhisperDirection::Unknown, partner: "へ".to_string() });
+  }
+
+  #[test]
+  fn keep_messages_without_speaker() {
+    assert_eq!(split_speaker(&Channel::Public, "Feverが 5% 回復しました。"), None);
+    assert_eq!(split_speaker(&Channel::Shout, "叫ぶ : 誰か [20thメモリアルボックス] でアイテムを獲得しました。 : ペットSスキルス"), None);
+    assert_eq!(split_speaker(&Channel::System, "STAB : 突き攻撃力に影響します。"), None);
+    assert_eq!(split_speaker(&Channel::Custom("取引".to_string()), "JADEN : 売ります"), None);
+  }
+}
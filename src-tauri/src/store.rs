@@ -0,0 +1,176 @@
+use std::{collections::{BTreeMap, VecDeque}, ops::Bound};
+use crate::{Channel, ChannelLog, ChatMessage};
+
+// Keeps every message once, by sequence number, with how many views hold it.
+// A view is only a list of sequence numbers, so more views cost little.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageStore {
+  messages: BTreeMap<u64, (ChatMessage, usize)>,
+  views: Vec<View>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct View {
+  channel: Channel,
+  seqs: VecDeque<u64>,
+  capacity: usize,
+  updated: bool,
+}
+
+impl MessageStore {
+  // The first view is the all view that every message goes to.
+  pub fn new(views: Vec<(Channel, usize)>) -> Self {
+    let views = views.into_iter().map(|(channel, capacity)| View { channel, seqs: VecDeque::new(), capacity, updated: false }).collect();
+    MessageStore { messages: BTreeMap::new(), views }
+  }
+
+  pub fn channels(&self) -> Vec<Channel> {
+    self.views.iter().map(|view| view.channel.clone()).collect()
+  }
+
+  pub fn view_index(&self, channel: &Channel) -> Option<usize> {
+    self.views.iter().position(|view| &view.channel == channel)
+  }
+
+  pub fn len(&self, view: usize) -> usize {
+    self.views[view].seqs.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.messages.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.messages.clear();
+    for view in self.views.iter_mut() {
+      view.seqs.clear();
+    }
+  }
+
+  pub fn clear_updated(&mut self) {
+    for view in self.views.iter_mut() {
+      view.updated = false;
+    }
+  }
+
+  // Adds a message to the all view and the view of its channel. A message
+  // that the latter had to drop to make room is handed back.
+  pub fn push(&mut self, message: ChatMessage, view: usize) -> Option<ChatMessage> {
+    let seq = message.seq;
+    self.messages.insert(seq, (message, 0));
+    let mut evicted = None;
+    for v in std::iter::once(0).chain(Some(view).filter(|&view| view != 0)) {
+      self.messages.get_mut(&seq).unwrap().1 += 1;
+      let old = match self.views[v].capacity <= self.views[v].seqs.len() {
+        true => self.views[v].seqs.pop_front(),
+        false => None,
+      };
+      self.views[v].seqs.push_back(seq);
+      self.views[v].updated = true;
+      if let Some(old) = old {
+        if v == view {
+          evicted = self.messages.get(&old).map(|(message, _)| message.clone());
+        }
+        self.release(old);
+      }
+    }
+    evicted
+  }
+
+  // A message goes as soon as no view holds it.
+  fn release(&mut self, seq: u64) {
+    if let Some((_, refs)) = self.messages.get_mut(&seq) {
+      *refs -= 1;
+      if *refs == 0 {
+        self.messages.remove(&seq);
+      }
+    }
+  }
+
+  pub fn iter(&self, view: usize) -> impl DoubleEndedIterator<Item = &ChatMessage> + '_ {
+    self.before(view, None)
+  }
+
+  // Messages of a view read before `seq`, or all of them.
+  pub fn before(&self, view: usize, seq: Option<u64>) -> impl DoubleEndedIterator<Item = &ChatMessage> + '_ {
+    let seqs = &self.views[view].seqs;
+    let end = match seq {
+      Some(seq) => seqs.partition_point(|&other| other < seq),
+      _ => seqs.len(),
+    };
+    seqs.range(..end).filter_map(move |seq| self.messages.get(seq).map(|(message, _)| message))
+  }
+
+  // Messages read after `seq`, or all of them.
+  pub fn after(&self, seq: Option<u64>) -> impl Iterator<Item = &ChatMessage> + '_ {
+    let start = match seq {
+      Some(seq) => Bound::Excluded(seq),
+      _ => Bound::Unbounded,
+    };
+    self.messages.range((start, Bound::Unbounded)).map(|(_, (message, _))| message)
+  }
+
+  pub fn logs(&self, shown: impl Fn(&ChatMessage) -> bool, limit: usize) -> Vec<ChannelLog> {
+    self.views.iter().enumerate().map(|(i, view)| {
+      let mut messages: VecDeque<_> = self.iter(i).rev().filter(|message| shown(message)).take(limit).cloned().collect();
+      messages.make_contiguous().reverse();
+      ChannelLog { channel: view.channel.clone(), messages, updated: view.updated }
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing;
+
+  fn message(seq: u64, channel: Channel) -> ChatMessage {
+    testing::message(seq, &seq.to_string(), "#ffffff", channel, (10, 0, 0))
+  }
+
+  fn seqs<'a>(messages: impl Iterator<Item = &'a ChatMessage>) -> Vec<u64> {
+    messages.map(|message| message.seq).collect()
+  }
+
+  #[test]
+  fn shared_messages() {
+    let mut store = MessageStore::new(vec![(Channel::All, 3), (Channel::Public, 2), (Channel::System, 2)]);
+    assert_eq!(store.push(message(0, Channel::Public), 1), None);
+    assert_eq!(store.push(message(1, Channel::System), 2), None);
+    assert_eq!(store.push(message(2, Channel::Public), 1), None);
+    assert_eq!(store.push(message(3, Channel::Public), 1), Some(message(0, Channel::Public)));
+    assert_eq!(seqs(store.iter(0)), [1, 2, 3]);
+    assert_eq!(seqs(store.iter(1)), [2, 3]);
+    assert_eq!(seqs(store.iter(2)), [1]);
+    assert_eq!(seqs(store.after(None)), [1, 2, 3]);
+    assert_eq!(seqs(store.after(Some(1))), [2, 3]);
+    assert_eq!(seqs(store.before(0, Some(3))), [1, 2]);
+    assert!(seqs(store.before(1, Some(2))).is_empty());
+
+    // Dropped from the all view but still in its channel's view.
+    store.push(message(4, Channel::Public), 1);
+    assert_eq!(seqs(store.iter(0)), [2, 3, 4]);
+    assert_eq!(seqs(store.iter(2)), [1]);
+    assert_eq!(seqs(store.after(None)), [1, 2, 3, 4]);
+
+    let logs = store.logs(|message| message.seq != 3, 1);
+    assert_eq!(seqs(logs[0].messages.iter()), [4]);
+    assert_eq!(seqs(logs[1].messages.iter()), [4]);
+    assert!(logs.iter().all(|log| log.updated));
+    store.clear_updated();
+    store.clear();
+    assert!(store.is_empty());
+    assert_eq!(store.len(0), 0);
+  }
+
+  #[test]
+  fn bounded_by_capacity() {
+    let mut store = MessageStore::new(vec![(Channel::All, 3), (Channel::Whisper, 3), (Channel::System, 3)]);
+    store.push(message(0, Channel::Whisper), 1);
+    for seq in 1..10000 {
+      store.push(message(seq, Channel::System), 2);
+      assert!(store.messages.len() <= 9);
+    }
+    assert_eq!(seqs(store.after(None)), [0, 9997, 9998, 9999]);
+  }
+}
@@ -0,0 +1,108 @@
+use encoding_rs::SHIFT_JIS;
+
+// Chat log writes can land in the middle of a line or of a two-byte Shift_JIS
+// character, so unterminated bytes stay in `pending` until the rest of them is
+// written. No Shift_JIS trail byte is CR or LF, so lines are split before they
+// are decoded and each keeps the file offset it starts at.
+#[derive(Debug, Default)]
+pub struct TailReader {
+  pending: Vec<u8>,
+  offset: u64,
+}
+
+impl TailReader {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn reset(&mut self) {
+    self.seek(0);
+  }
+
+  // Starts over at `offset` of the file, e.g. past the content we skip.
+  pub fn seek(&mut self, offset: u64) {
+    self.pending.clear();
+    self.offset = offset;
+  }
+
+  // The file offset of the pending text.
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  // The pending text up to the last whole character.
+  pub fn pending(&self) -> String {
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(decoder.max_utf8_buffer_length(self.pending.len()).unwrap_or(self.pending.len() * 3));
+    let _ = decoder.decode_to_string(&self.pending, &mut text, false);
+    text
+  }
+
+  pub fn feed(&mut self, bytes: &[u8]) -> Vec<(u64, String)> {
+    self.pending.extend_from_slice(bytes);
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while let Some(end) = self.pending[start..].windows(2).position(|window| window == b"\r\n") {
+      let (line, _) = SHIFT_JIS.decode_without_bom_handling(&self.pending[start..start + end]);
+      if !line.trim().is_empty() {
+        lines.push((self.offset + start as u64, line.into_owned()));
+      }
+      start += end + 2;
+    }
+    self.pending.drain(..start);
+    self.offset += start as u64;
+    lines
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn texts(lines: Vec<(u64, String)>) -> Vec<String> {
+    lines.into_iter().map(|(_, line)| line).collect()
+  }
+
+  #[test]
+  fn feed_complete_lines() {
+    let mut reader = TailReader::new();
+    let (bytes, _, _) = SHIFT_JIS.encode("<b>一行目</b></br>\r\n\r\n<b>二行目</b></br>\r\n");
+    let lines = reader.feed(&bytes);
+    assert_eq!(lines.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(), [0, 22]);
+    assert_eq!(texts(lines), vec!["<b>一行目</b></br>", "<b>二行目</b></br>"]);
+    assert_eq!(reader.pending(), "");
+    assert_eq!(reader.offset(), bytes.len() as u64);
+    reader.seek(100);
+    assert_eq!(reader.feed(&bytes)[1].0, 122);
+  }
+
+  #[test]
+  fn feed_partial_line() {
+    let mut reader = TailReader::new();
+    let (bytes, _, _) = SHIFT_JIS.encode("<b>一行目</b></br>\r\n<b>二行");
+    assert_eq!(texts(reader.feed(&bytes)), vec!["<b>一行目</b></br>"]);
+    assert_eq!(reader.pending(), "<b>二行");
+    let (bytes, _, _) = SHIFT_JIS.encode("目</b></br>\r");
+    assert_eq!(texts(reader.feed(&bytes)), Vec::<String>::new());
+    assert_eq!(texts(reader.feed(b"\n")), vec!["<b>二行目</b></br>"]);
+    assert_eq!(reader.pending(), "");
+  }
+
+  #[test]
+  fn feed_split_character() {
+    let mut reader = TailReader::new();
+    let (bytes, _, _) = SHIFT_JIS.encode("経験値</br>\r\n");
+    for i in 0..bytes.len() - 1 {
+      let mut reader = TailReader::new();
+      assert_eq!(texts(reader.feed(&bytes[..i])), Vec::<String>::new());
+      assert_eq!(texts(reader.feed(&bytes[i..])), vec!["経験値</br>"]);
+    }
+    for byte in bytes.iter() {
+      let lines = texts(reader.feed(&[*byte]));
+      assert!(reader.pending().chars().all(|c| c != '\u{fffd}'));
+      if !lines.is_empty() {
+        assert_eq!(lines, vec!["経験値</br>"]);
+      }
+    }
+  }
+}
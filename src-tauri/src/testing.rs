@@ -0,0 +1,67 @@
+// Helpers shared by the tests of every module.
+use std::{fs, path::{Path, PathBuf}};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use crate::{jst, Channel, ChatMessage, PRIMARY_SOURCE};
+
+// Test logs are found from the manifest so tests pass from any directory.
+pub fn fixture_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("test")
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+  fixture_dir().join(name)
+}
+
+pub fn log_date() -> NaiveDate {
+  NaiveDate::from_ymd_opt(2024, 4, 20).unwrap()
+}
+
+pub fn utc(text: &str) -> DateTime<Utc> {
+  Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(text, "%Y/%m/%d %H:%M:%S").unwrap())
+}
+
+// A message of the primary source, read on `log_date`.
+pub fn message(seq: u64, text: &str, color: &str, channel: Channel, time: (u32, u32, u32)) -> ChatMessage {
+  let time = jst(log_date(), NaiveTime::from_hms_opt(time.0, time.1, time.2).unwrap());
+  ChatMessage { seq, source: PRIMARY_SOURCE.to_string(), speaker: None, whisper: None, text: text.to_string(), color: color.to_string(), channel, time, backfilled: false }
+}
+
+// A chat line as the game writes it, without the line break.
+pub fn line(time: &str, color: &str, text: &str) -> String {
+  format!(r##"<font size="2" color="white"> {} </font> <font size="2" color="{}">{}</font></br>"##, time, color, text)
+}
+
+// A system message line as it is in the file.
+pub fn log_line(time: &str, text: &str) -> Vec<u8> {
+  encoding_rs::SHIFT_JIS.encode(&(line(time, "#ff64ff", text) + "\r\n")).0.into_owned()
+}
+
+pub fn log_header(date: NaiveDate) -> Vec<u8> {
+  let header = format!(concat!(
+    "<body bgcolor=\"black\">\r\n",
+    "<font size=\"2\" color=\"white\"> <b> Talesweaver Chat Message Log </b></font></br>\r\n",
+    "<font size=\"2\" color=\"white\"> <b> Date : {}年 {:>4}月 {:>4}日 </b></font></br></br></br>\r\n",
+    "<font face=\"ＭＳ ゴシック\">\r\n",
+  ), date.year(), date.month(), date.day());
+  encoding_rs::SHIFT_JIS.encode(&header).0.into_owned()
+}
+
+// A folder of one test that is not created here but is removed afterwards,
+// even when the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  pub fn new(name: &str) -> Self {
+    TempDir(std::env::temp_dir().join(format!("neos-{}-{}", name, std::process::id())))
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
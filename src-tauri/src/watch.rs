@@ -0,0 +1,163 @@
+use std::{path::{Path, PathBuf}, sync::mpsc::Sender, time::Duration};
+use anyhow::{Context, Result};
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::log_file_date;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Watch {
+  Native,
+  Polling,
+  // Not there yet, e.g. before the game is first run. Tried again by retry.
+  Missing,
+  Failed,
+}
+
+// Wakes the tailer when a TWChatLog file in a log directory changes. A
+// directory where change notifications are unavailable, e.g. on a network
+// drive or some Wine setups, is polled instead while the rest stay native.
+pub struct LogWatcher {
+  tx: Sender<()>,
+  native: Option<RecommendedWatcher>,
+  poll: Option<PollWatcher>,
+  interval: Duration,
+  dirs: Vec<(PathBuf, Watch)>,
+}
+
+impl std::fmt::Debug for LogWatcher {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("LogWatcher").field("native", &self.native.is_some()).field("dirs", &self.dirs).finish()
+  }
+}
+
+impl LogWatcher {
+  pub fn new(tx: Sender<()>) -> Result<Self> {
+    let native = RecommendedWatcher::new(handler(tx.clone()), Config::default()).ok();
+    Ok(LogWatcher { tx, native, poll: None, interval: POLL_INTERVAL, dirs: Vec::new() })
+  }
+
+  pub fn polling(tx: Sender<()>, interval: Duration) -> Result<Self> {
+    Ok(LogWatcher { tx, native: None, poll: None, interval, dirs: Vec::new() })
+  }
+
+  pub fn is_polling(&self, dir: &Path) -> bool {
+    self.dirs.iter().any(|(other, watch)| other == dir && *watch == Watch::Polling)
+  }
+
+  pub fn is_watching(&self, dir: &Path) -> bool {
+    self.dirs.iter().any(|(other, watch)| other == dir && matches!(watch, Watch::Native | Watch::Polling))
+  }
+
+  // Wakes the tailer without a change, e.g. when what it reads is replaced.
+  pub fn wake(&self) {
+    let _ = self.tx.send(());
+  }
+
+  // Whether a directory is not there yet, so retry has something to do.
+  pub fn is_waiting(&self) -> bool {
+    self.dirs.iter().any(|(_, watch)| *watch == Watch::Missing)
+  }
+
+  // Replaces the watched directories, one per log source. A directory that
+  // cannot be watched at all is reported, and the others are watched anyway.
+  pub fn watch(&mut self, dirs: &[PathBuf]) -> Result<()> {
+    for (dir, watch) in std::mem::take(&mut self.dirs) {
+      self.unwatch_dir(&dir, watch);
+    }
+    let mut result = Ok(());
+    for dir in dirs {
+      let watch = match self.watch_dir(dir) {
+        Ok(watch) => watch,
+        Err(e) => {
+          result = Err(e);
+          Watch::Failed
+        }
+      };
+      self.dirs.push((dir.clone(), watch));
+    }
+    result
+  }
+
+  // Watches the directories that were missing and have since been created.
+  // One that then fails is reported once and left alone.
+  pub fn retry(&mut self) -> Result<()> {
+    let mut result = Ok(());
+    for i in 0..self.dirs.len() {
+      if self.dirs[i].1 != Watch::Missing || !self.dirs[i].0.is_dir() {
+        continue;
+      }
+      let dir = self.dirs[i].0.clone();
+      self.dirs[i].1 = match self.watch_dir(&dir) {
+        Ok(watch) => watch,
+        Err(e) => {
+          result = Err(e);
+          Watch::Failed
+        }
+      };
+    }
+    result
+  }
+
+  fn watch_dir(&mut self, dir: &Path) -> Result<Watch> {
+    if !dir.is_dir() {
+      return Ok(Watch::Missing);
+    }
+    if let Some(native) = self.native.as_mut() {
+      if native.watch(dir, RecursiveMode::NonRecursive).is_ok() {
+        return Ok(Watch::Native);
+      }
+    }
+    if self.poll.is_none() {
+      self.poll = Some(PollWatcher::new(handler(self.tx.clone()), Config::default().with_poll_interval(self.interval))?);
+    }
+    let poll = self.poll.as_mut().unwrap();
+    poll.watch(dir, RecursiveMode::NonRecursive).with_context(|| format!("{} を監視できません。", dir.display()))?;
+    Ok(Watch::Polling)
+  }
+
+  fn unwatch_dir(&mut self, dir: &Path, watch: Watch) {
+    let _ = match (watch, self.native.as_mut(), self.poll.as_mut()) {
+      (Watch::Native, Some(native), _) => native.unwatch(dir),
+      (Watch::Polling, _, Some(poll)) => poll.unwatch(dir),
+      _ => Ok(()),
+    };
+  }
+}
+
+fn handler(tx: Sender<()>) -> impl FnMut(notify::Result<Event>) + Send + 'static {
+  move |event: notify::Result<Event>| {
+    let wake = match event {
+      Ok(event) => event.paths.iter().any(|path| path.file_name().and_then(|name| name.to_str()).and_then(log_file_date).is_some()),
+      _ => true,
+    };
+    if wake {
+      let _ = tx.send(());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::TempDir;
+  use std::{fs, sync::mpsc};
+
+  #[test]
+  fn poll_log_dir() {
+    let temp = TempDir::new("watch");
+    let dir = temp.path().to_path_buf();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = LogWatcher::polling(tx, Duration::from_millis(20)).unwrap();
+    watcher.watch(std::slice::from_ref(&dir)).unwrap();
+    assert!(!watcher.is_watching(&dir));
+    assert!(watcher.is_waiting());
+    fs::create_dir_all(&dir).unwrap();
+    watcher.retry().unwrap();
+    assert!(!watcher.is_waiting());
+    fs::write(dir.join("memo.txt"), "memo").unwrap();
+    fs::write(dir.join("TWChatLog_2024_04_20.html"), "<body").unwrap();
+    assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    assert!(watcher.is_polling(&dir));
+  }
+}